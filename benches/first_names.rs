@@ -4,33 +4,83 @@ use std::sync::Arc;
 
 const NAME_DATASET: &str = include_str!("US_Census_1990_Frequent_Male_First_Names.txt");
 
-const SEQUENCE_START: char = '\x01';
+/// Size of the synthetic alphabet used to benchmark construction with large (e.g. CJK) alphabets.
+const LARGE_ALPHABET_SIZE: u32 = 5000;
+const LARGE_ALPHABET_LINES: usize = 20000;
 
-const GRAPH_STEPPER_CONFIG: GraphStepperConfiguration = GraphStepperConfiguration {
-    start_char: Some(SEQUENCE_START),
+const GRAPH_STEPPER_CONFIG: GraphStepperConfiguration<char> = GraphStepperConfiguration {
+    start_value: None,
     min_length: None,
+    max_length: None,
+    exact_length: None,
+    prefix: Vec::new(),
+    suffix: Vec::new(),
+    banned_values: Vec::new(),
+    required_sequences: Vec::new(),
+    min_edit_distance: None,
+    temperature: None,
+    top_k: None,
+    top_p: None,
+    backoff_threshold: None,
 };
 
+/// Builds a dataset of random CJK ideograph sequences, drawn from a fixed seed so that runs stay comparable.
+fn large_alphabet_dataset() -> Vec<Vec<char>> {
+    let mut rng = fastrand::Rng::with_seed(0);
+    (0..LARGE_ALPHABET_LINES)
+        .map(|_| {
+            (0..rng.usize(2..8))
+                .map(|_| char::from_u32(0x4E00 + rng.u32(0..LARGE_ALPHABET_SIZE)).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("construct graph", |b| {
         b.iter(|| {
             let mut constructor = GraphConstructor::new();
             NAME_DATASET.lines().for_each(|l| {
-                l.chars().fold(SEQUENCE_START, |acc, x| {
-                    constructor.register_sequence(acc, x);
-                    x
-                });
+                constructor.register_whole_sequence(l.chars());
+            });
+            constructor.construct();
+        })
+    });
+
+    let large_alphabet_dataset = large_alphabet_dataset();
+    c.bench_function("construct graph (large alphabet)", |b| {
+        b.iter(|| {
+            let mut constructor = GraphConstructor::new();
+            large_alphabet_dataset.iter().for_each(|l| {
+                constructor.register_whole_sequence(l.iter().copied());
             });
             constructor.construct();
         })
     });
 
+    let mut constructor = GraphConstructor::new();
+    large_alphabet_dataset.iter().for_each(|l| {
+        constructor.register_whole_sequence(l.iter().copied());
+    });
+    let large_alphabet_graph = Arc::new(constructor.construct());
+    drop(large_alphabet_dataset);
+
+    let mut stepper = GraphStepper::new(large_alphabet_graph, GRAPH_STEPPER_CONFIG)
+        .expect("Unable to build stepper");
+    c.bench_function(
+        "pre-built stepper stepping once and resetting (large alphabet)",
+        |b| {
+            b.iter(|| {
+                let _ = stepper.step();
+                let _ = stepper.flush();
+            });
+        },
+    );
+    drop(stepper);
+
     let mut constructor = GraphConstructor::new();
     NAME_DATASET.lines().for_each(|l| {
-        l.chars().fold(SEQUENCE_START, |acc, x| {
-            constructor.register_sequence(acc, x);
-            x
-        });
+        constructor.register_whole_sequence(l.chars());
     });
     let graph = Arc::new(constructor.construct());
 
@@ -0,0 +1,100 @@
+/// Walker's alias method, as described by Vose: after O(n) preprocessing, every sample takes O(1) time
+/// regardless of the number of outcomes.
+#[derive(Clone, Default)]
+pub(crate) struct AliasTable {
+    /// Probability of keeping the uniformly chosen outcome instead of switching to its alias.
+    thresholds: Vec<f32>,
+    aliases: Vec<u32>,
+}
+
+impl AliasTable {
+    /// Builds a table from non-negative weights, which don't need to be normalized.
+    /// Outcomes with a weight of 0 are never sampled, as long as any weight is positive.
+    pub(crate) fn new(weights: &[f32]) -> Self {
+        let count = weights.len();
+        let weight_sum: f64 = weights.iter().map(|x| *x as f64).sum();
+
+        let mut thresholds = vec![1.0; count];
+        let mut aliases: Vec<u32> = (0..count as u32).collect();
+        if count == 0 || weight_sum <= 0.0 {
+            return Self {
+                thresholds,
+                aliases,
+            };
+        }
+
+        let mut scaled: Vec<f64> = weights
+            .iter()
+            .map(|x| *x as f64 * count as f64 / weight_sum)
+            .collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..count).partition(|index| scaled[*index] < 1.0);
+
+        while let (Some(less), Some(more)) = (small.pop(), large.last().copied()) {
+            thresholds[less] = scaled[less] as f32;
+            aliases[less] = more as u32;
+
+            scaled[more] -= 1.0 - scaled[less];
+            if scaled[more] < 1.0 {
+                large.pop();
+                small.push(more);
+            }
+        }
+
+        // Whatever is left over is 1.0 save for floating point error, so these outcomes always keep their slot.
+        for index in small.into_iter().chain(large) {
+            thresholds[index] = 1.0;
+        }
+
+        Self {
+            thresholds,
+            aliases,
+        }
+    }
+
+    /// Returns the index of the sampled outcome, or `None` if the table is empty.
+    pub(crate) fn sample(&self, rng: &mut fastrand::Rng) -> Option<usize> {
+        if self.thresholds.is_empty() {
+            return None;
+        }
+
+        let index = rng.usize(0..self.thresholds.len());
+        if rng.f32() < self.thresholds[index] {
+            Some(index)
+        } else {
+            Some(self.aliases[index] as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_frequencies_match_weights() {
+        let weights = [1.0, 0.0, 3.0, 6.0, 0.0];
+        let table = AliasTable::new(&weights);
+        let mut rng = fastrand::Rng::with_seed(7);
+
+        let mut counts = [0usize; 5];
+        let sample_count = 100_000;
+        for _ in 0..sample_count {
+            counts[table.sample(&mut rng).unwrap()] += 1;
+        }
+
+        let weight_sum: f32 = weights.iter().sum();
+        for (count, weight) in counts.iter().zip(weights) {
+            let frequency = *count as f32 / sample_count as f32;
+            assert!((frequency - weight / weight_sum).abs() < 0.01);
+        }
+        assert_eq!(counts[1], 0);
+        assert_eq!(counts[4], 0);
+    }
+
+    #[test]
+    fn empty_table_samples_nothing() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        assert_eq!(AliasTable::new(&[]).sample(&mut rng), None);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{alias::AliasTable, sample_weighted, Graph, Symbol};
+
+/// Precomputed for [`crate::GraphStepperConfiguration::backoff_threshold`]: the transitions of every context shorter
+/// than the graph's order, pooled from all vertices whose context ends with it.
+pub(crate) struct BackoffTable {
+    /// Indexed by context length, from 0 up to the graph's order minus 1.
+    levels: Vec<Vec<BackoffContext>>,
+    /// The index of each vertex's shorter contexts in `levels`, at `vertex_index * order + length`.
+    vertex_contexts: Vec<usize>,
+    order: usize,
+}
+
+struct BackoffContext {
+    /// Index of the source vertex and of the edge within it, for every pooled transition.
+    edges: Vec<(usize, usize)>,
+    count: usize,
+    sampler: AliasTable,
+}
+
+impl BackoffTable {
+    pub(crate) fn new<T: Eq + Hash + Clone>(graph: &Graph<T>) -> Self {
+        let order = graph.order;
+        let mut levels: Vec<Vec<BackoffContext>> = Vec::with_capacity(order);
+        let mut vertex_contexts = vec![0; graph.vertices.len() * order];
+
+        for length in 0..order {
+            let mut context_indices: HashMap<&[Symbol<T>], usize> = HashMap::new();
+            let mut level: Vec<BackoffContext> = Vec::new();
+
+            for (vertex_index, vertex) in graph.vertices.iter().enumerate() {
+                let context = &vertex.context[order - length..];
+                let index = *context_indices.entry(context).or_insert_with(|| {
+                    level.push(BackoffContext {
+                        edges: Vec::new(),
+                        count: 0,
+                        sampler: AliasTable::new(&[]),
+                    });
+                    level.len() - 1
+                });
+                vertex_contexts[vertex_index * order + length] = index;
+
+                let pooled = &mut level[index];
+                for (edge_index, edge) in vertex.edges.iter().enumerate() {
+                    pooled.edges.push((vertex_index, edge_index));
+                    pooled.count += edge.count;
+                }
+            }
+
+            for context in level.iter_mut() {
+                let weights: Vec<f32> = context
+                    .edges
+                    .iter()
+                    .map(|(vertex, edge)| graph.vertices[*vertex].edges[*edge].count as f32)
+                    .collect();
+                context.sampler = AliasTable::new(&weights);
+            }
+
+            levels.push(level);
+        }
+
+        Self {
+            levels,
+            vertex_contexts,
+            order,
+        }
+    }
+
+    /// The longest context the vertex with index `vertex_index` backs off to, i.e. the longest one observed at
+    /// least `threshold` times. Falls back to the empty context.
+    pub(crate) fn context_length(&self, vertex_index: usize, threshold: usize) -> usize {
+        (1..self.order)
+            .rev()
+            .find(|x| self.context(vertex_index, *x).count >= threshold)
+            .unwrap_or(0)
+    }
+
+    /// Samples a transition of the context of `length` that the vertex with index `vertex_index` ends with, by
+    /// count, leaving out transitions into dead ends if `avoid_dead_ends` is set and others are left. The target
+    /// is the vertex that the vertex with index `vertex_index` would transition to given the sampled value, if that
+    /// transition was observed, or otherwise the vertex agreeing with it on the most recent values. `None` if the
+    /// context was never observed.
+    pub(crate) fn sample<T: PartialEq>(
+        &self,
+        graph: &Graph<T>,
+        vertex_index: usize,
+        length: usize,
+        avoid_dead_ends: bool,
+        rng: &mut fastrand::Rng,
+    ) -> Option<usize> {
+        let context = self.context(vertex_index, length);
+        let target_of =
+            |(source, edge): &(usize, usize)| graph.vertices[*source].edges[*edge].vertex_index;
+        let is_dead_end = |x: &(usize, usize)| graph.vertices[target_of(x)].edges.is_empty();
+
+        let selection = if avoid_dead_ends && context.edges.iter().any(|x| !is_dead_end(x)) {
+            let weights: Vec<f64> = context
+                .edges
+                .iter()
+                .map(|x| match is_dead_end(x) {
+                    true => 0.0,
+                    false => graph.vertices[x.0].edges[x.1].count as f64,
+                })
+                .collect();
+            sample_weighted(rng, &weights)?
+        } else {
+            context.sampler.sample(rng)?
+        };
+        let target = target_of(&context.edges[selection]);
+        let value = graph.vertices[target].value();
+
+        // Every longer context was observed less often than the threshold, so they hold few transitions.
+        for longer in (length + 1..self.order).rev() {
+            let found = self
+                .context(vertex_index, longer)
+                .edges
+                .iter()
+                .map(target_of)
+                .find(|x| graph.vertices[*x].value() == value);
+            if found.is_some() {
+                return found;
+            }
+        }
+
+        Some(target)
+    }
+
+    fn context(&self, vertex_index: usize, length: usize) -> &BackoffContext {
+        &self.levels[length][self.vertex_contexts[vertex_index * self.order + length]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::names_graph;
+
+    fn context(values: &str) -> Vec<Symbol<char>> {
+        values.chars().map(Symbol::Value).collect()
+    }
+
+    #[test]
+    fn context_length_backs_off_until_observed_often_enough() {
+        let graph = names_graph();
+        let table = BackoffTable::new(&graph);
+        let vertex = graph.find_vertex(&context("ja")).unwrap();
+
+        assert_eq!(table.context_length(vertex, 1), 1);
+        assert_eq!(table.context_length(vertex, 1000), 0);
+    }
+
+    #[test]
+    fn empty_context_samples_values_by_overall_frequency() {
+        let graph = names_graph();
+        let table = BackoffTable::new(&graph);
+        let vertex = graph.find_vertex(&context("an")).unwrap();
+
+        let mut expected: HashMap<&Symbol<char>, f64> = HashMap::new();
+        let mut count_sum = 0.0;
+        for edge in graph.vertices.iter().flat_map(|x| x.edges.iter()) {
+            *expected
+                .entry(graph.vertices[edge.vertex_index].value())
+                .or_default() += edge.count as f64;
+            count_sum += edge.count as f64;
+        }
+
+        let mut rng = fastrand::Rng::with_seed(7);
+        let mut sampled: HashMap<&Symbol<char>, f64> = HashMap::new();
+        for _ in 0..50_000 {
+            let target = table.sample(&graph, vertex, 0, false, &mut rng).unwrap();
+            *sampled.entry(graph.vertices[target].value()).or_default() += 1.0 / 50_000.0;
+        }
+
+        for (value, count) in expected {
+            let frequency = sampled.get(value).copied().unwrap_or(0.0);
+            assert!((frequency - count / count_sum).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn sampled_values_continue_the_longest_context() {
+        let graph = names_graph();
+        let table = BackoffTable::new(&graph);
+        let vertex = graph.find_vertex(&context("an")).unwrap();
+
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..1000 {
+            let target = table.sample(&graph, vertex, 0, false, &mut rng).unwrap();
+            let value = graph.vertices[target].value();
+
+            // Values observed after "an" lead to the vertex that continues it.
+            if let Some(edge) = graph.vertices[vertex]
+                .edges
+                .iter()
+                .find(|x| graph.vertices[x.vertex_index].value() == value)
+            {
+                assert_eq!(target, edge.vertex_index);
+            }
+        }
+    }
+
+    #[test]
+    fn avoiding_dead_ends_never_samples_them() {
+        let graph = names_graph();
+        let table = BackoffTable::new(&graph);
+        let vertex = graph.find_vertex(&context("an")).unwrap();
+
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..1000 {
+            let target = table.sample(&graph, vertex, 0, true, &mut rng).unwrap();
+            assert!(!graph.vertices[target].edges.is_empty());
+        }
+    }
+}
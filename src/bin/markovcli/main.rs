@@ -0,0 +1,1056 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::Shell;
+use markovgen::*;
+use serde::{Deserialize, Serialize};
+use tokenizer::{Token, Tokenizer};
+
+mod tokenizer;
+
+#[derive(Parser)]
+#[command(name = "markovcli", version, about)]
+struct CliArgs {
+    #[command(subcommand)]
+    command: Subcommands,
+}
+
+// Parsed once, so the size of the largest variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Subcommands {
+    #[command(about = "Build a markov chain graph from a dataset of sequences.")]
+    Compile {
+        #[arg(help = "Path to an input file with sequences separated by newlines.", value_hint = ValueHint::FilePath)]
+        input_path: PathBuf,
+        #[arg(help = "Defaults to [input_name].graph.bin", value_hint = ValueHint::FilePath)]
+        output_path: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Number of preceding tokens each transition depends on.",
+            default_value = "1",
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        order: u64,
+        #[arg(
+            long,
+            value_enum,
+            help = "How lines are split into tokens.",
+            default_value = "chars"
+        )]
+        tokenizer: Tokenizer,
+        #[arg(
+            long,
+            help = "Read sequences right-to-left, so that the graph can generate sequences with a given --suffix."
+        )]
+        reverse: bool,
+        #[arg(
+            long,
+            help = "Store the input sequences in the graph, so that sampling can reject reproductions of them."
+        )]
+        keep_sequences: bool,
+        #[command(flatten)]
+        smoothing: SmoothingArgs,
+    },
+    #[command(about = "Add sequences from a dataset to a previously compiled graph.")]
+    Update {
+        #[arg(help = "Path to a previously compiled graph.", value_hint = ValueHint::FilePath)]
+        graph_path: PathBuf,
+        #[arg(help = "Path to an input file with sequences separated by newlines.", value_hint = ValueHint::FilePath)]
+        input_path: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "Defaults to overwriting the graph at GRAPH_PATH",
+            value_hint = ValueHint::FilePath
+        )]
+        output_path: Option<PathBuf>,
+    },
+    #[command(about = "Combine previously compiled graphs into one.")]
+    Merge {
+        #[arg(
+            help = "Paths to previously compiled graphs, built with the same order and tokenizer.",
+            required = true,
+            num_args = 2..,
+            value_hint = ValueHint::FilePath
+        )]
+        graph_paths: Vec<PathBuf>,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Comma-separated share of each graph in the result. Without weights, transition counts are simply added up."
+        )]
+        weights: Option<Vec<f64>>,
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        output_path: PathBuf,
+    },
+    #[command(about = "Sample a sequence from a previously compiled graph.")]
+    Sample {
+        #[arg(help = "Path to a previously compiled graph.", value_hint = ValueHint::FilePath)]
+        graph_path: PathBuf,
+        #[arg(help = "The amount of sequences to sample.", default_value = "1")]
+        count: usize,
+        #[arg(
+            long,
+            help = "Minimum length of sample in tokens.",
+            default_value = "3"
+        )]
+        min_length: usize,
+        #[arg(
+            long,
+            help = "Steer towards ending sequences before they exceed this many tokens."
+        )]
+        max_length: Option<usize>,
+        #[arg(
+            long,
+            help = "Only generate sequences of exactly this many tokens. Raises --max-tokens above this length if needed.",
+            conflicts_with_all = ["min_length", "max_length"]
+        )]
+        exact_length: Option<usize>,
+        #[arg(long, help = "Only generate sequences starting with this text.")]
+        prefix: Option<String>,
+        #[arg(
+            long,
+            help = "Only generate sequences ending with this text. Requires a graph compiled with --reverse."
+        )]
+        suffix: Option<String>,
+        #[arg(long, help = "Never generate any of the tokens in this text.")]
+        ban: Option<String>,
+        #[arg(
+            long,
+            help = "Only generate sequences containing this text. Can be given multiple times."
+        )]
+        require: Vec<String>,
+        #[arg(
+            long,
+            help = "Reject sequences that appear in the dataset. Requires a graph compiled with --keep-sequences.",
+            conflicts_with = "min_edit_distance"
+        )]
+        novel: bool,
+        #[arg(
+            long,
+            help = "Reject sequences fewer than this many token edits away from any sequence in the dataset. Requires a graph compiled with --keep-sequences."
+        )]
+        min_edit_distance: Option<usize>,
+        #[arg(
+            long,
+            help = "Below 1, makes likely transitions even more likely. Above 1, makes the output more varied."
+        )]
+        temperature: Option<f64>,
+        #[arg(long, help = "Only pick among the K most likely next tokens.")]
+        top_k: Option<usize>,
+        #[arg(
+            long,
+            help = "Only pick among the most likely next tokens, up to this cumulative probability."
+        )]
+        top_p: Option<f64>,
+        #[arg(
+            long,
+            help = "Pick the next token based on fewer preceding tokens when the current ones were seen fewer than this many times in the dataset.",
+            value_parser = clap::value_parser!(u64).range(1..),
+            conflicts_with_all = ["exact_length", "ban", "require"]
+        )]
+        backoff: Option<u64>,
+        #[arg(long, help = "Only print distinct sequences.")]
+        unique: bool,
+        #[arg(
+            long,
+            help = "With --unique, assume no more distinct sequences can be found after this many duplicates in a row.",
+            default_value = "1000",
+            requires = "unique"
+        )]
+        max_duplicates: usize,
+        #[arg(
+            long,
+            help = "Maximum number of tokens generated per sequence",
+            default_value = "64"
+        )]
+        max_tokens: usize,
+        #[arg(
+            long,
+            help = "Give up after this many attempts at sampling a sequence. Defaults to 100 times COUNT."
+        )]
+        max_attempts: Option<usize>,
+        #[arg(
+            long,
+            help = "Print sequences that hit --max-tokens or ran out of edges instead of discarding them."
+        )]
+        keep_incomplete: bool,
+        #[arg(
+            long,
+            help = "Seed for the random number generator, making the output reproducible."
+        )]
+        seed: Option<u64>,
+    },
+    #[command(about = "List the most likely sequences of a previously compiled graph.")]
+    Top {
+        #[arg(help = "Path to a previously compiled graph.", value_hint = ValueHint::FilePath)]
+        graph_path: PathBuf,
+        #[arg(
+            help = "The amount of sequences to list.",
+            default_value = "10",
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        count: u64,
+        #[arg(
+            long,
+            help = "Only list the sequence built by always picking the most likely next token.",
+            conflicts_with = "count"
+        )]
+        greedy: bool,
+        #[arg(
+            long,
+            help = "Maximum number of tokens per sequence",
+            default_value = "64"
+        )]
+        max_tokens: usize,
+    },
+    #[command(
+        about = "Print the log-probability of each line of a file under a previously compiled graph."
+    )]
+    Score {
+        #[arg(help = "Path to a previously compiled graph.", value_hint = ValueHint::FilePath)]
+        graph_path: PathBuf,
+        #[arg(help = "Path to an input file with sequences separated by newlines.", value_hint = ValueHint::FilePath)]
+        input_path: PathBuf,
+        #[arg(
+            long,
+            help = "Probability assigned to transitions the graph doesn't contain. Without it, lines containing them score -inf."
+        )]
+        unseen_probability: Option<f64>,
+        #[arg(
+            long,
+            help = "Divide scores by the number of transitions, so that lines of different lengths can be compared."
+        )]
+        per_token: bool,
+        #[arg(long, help = "Print the score of each transition below each line.")]
+        breakdown: bool,
+    },
+    #[command(
+        about = "Measure how well a previously compiled graph fits held-out sequences. --smoothing replaces the smoothing the graph was compiled with."
+    )]
+    Eval {
+        #[arg(
+            help = "Path to a previously compiled graph, or with --split, to a dataset to compile one from.",
+            value_hint = ValueHint::FilePath
+        )]
+        graph_path: PathBuf,
+        #[arg(
+            help = "Path to a file with held-out sequences separated by newlines.",
+            required_unless_present = "split",
+            conflicts_with = "split",
+            value_hint = ValueHint::FilePath
+        )]
+        heldout_path: Option<PathBuf>,
+        // Options that only apply with --split require this group rather than the argument itself, since clap
+        // doesn't enforce requirements on arguments conflicting with a present one, here the held-out path.
+        #[arg(
+            long,
+            group = "split_group",
+            help = "Hold out this share of the dataset's lines, picked at random, and compile a graph from the rest."
+        )]
+        split: Option<f64>,
+        #[arg(
+            long,
+            help = "With --split, the order of the compiled graph.",
+            default_value = "1",
+            requires = "split_group",
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        order: u64,
+        #[arg(
+            long,
+            value_enum,
+            help = "With --split, how lines are split into tokens.",
+            default_value = "chars",
+            requires = "split_group"
+        )]
+        tokenizer: Tokenizer,
+        #[arg(
+            long,
+            help = "Seed for picking the held-out lines with --split.",
+            requires = "split_group"
+        )]
+        seed: Option<u64>,
+        #[arg(
+            long,
+            help = "Probability assigned to transitions the graph doesn't contain. Without it, any unseen transition makes the cross-entropy infinite."
+        )]
+        unseen_probability: Option<f64>,
+        #[command(flatten)]
+        smoothing: SmoothingArgs,
+    },
+    #[command(
+        about = "Guess which of several previously compiled graphs each line most likely comes from. Scores with Kneser-Ney smoothing unless --smoothing is given, replacing the smoothing the graphs were compiled with."
+    )]
+    Classify {
+        #[arg(
+            long = "model",
+            value_name = "NAME=PATH",
+            help = "A class name and the path to its compiled graph. Repeat for every class.",
+            required = true,
+            value_parser = parse_named_path
+        )]
+        models: Vec<(String, PathBuf)>,
+        #[arg(help = "Path to an input file with sequences separated by newlines.", value_hint = ValueHint::FilePath)]
+        input_path: PathBuf,
+        #[arg(
+            long,
+            help = "Print the probability of every class rather than just the most likely one."
+        )]
+        all: bool,
+        #[command(flatten)]
+        smoothing: SmoothingArgs,
+    },
+    #[command(about = "Generate shell completion script to STDOUT.")]
+    GenerateCompletions {
+        #[arg(value_enum)]
+        shell_generator: Shell,
+    },
+}
+
+/// A compiled graph along with the tokenizer that was used to build it, as written to disk.
+#[derive(Serialize, Deserialize)]
+struct Model {
+    tokenizer: Tokenizer,
+    graph: Graph<Token>,
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    match args.command {
+        Subcommands::GenerateCompletions { shell_generator } => {
+            let mut command = CliArgs::command();
+            let command = &mut command;
+
+            clap_complete::generate(
+                shell_generator,
+                command,
+                command.get_name().to_string(),
+                &mut io::stdout(),
+            );
+        }
+
+        Subcommands::Compile {
+            input_path: input,
+            output_path: output,
+            order,
+            tokenizer,
+            reverse,
+            keep_sequences,
+            smoothing,
+        } => {
+            let Some(smoothing) = parse_smoothing(smoothing) else {
+                return;
+            };
+            let Some(lines) = read_lines(&input) else {
+                return;
+            };
+
+            let mut constructor = GraphConstructor::with_order(order as usize);
+            if reverse {
+                constructor = constructor.reversed();
+            }
+            if keep_sequences {
+                constructor = constructor.keep_sequences();
+            }
+            if let Some(smoothing) = smoothing {
+                constructor = constructor.smoothing(smoothing);
+            }
+            register_lines(&mut constructor, tokenizer, &lines);
+
+            println!("Constructed graph. Now computing probabilities.");
+            let graph = constructor.construct();
+
+            println!("Done. Writing file.");
+            let output_path: PathBuf = match output {
+                None => {
+                    let mut out = input.clone();
+                    out.set_extension("graph.bin");
+
+                    out
+                }
+                Some(x) => x,
+            };
+            save_model(&output_path, &Model { tokenizer, graph });
+        }
+
+        Subcommands::Update {
+            graph_path,
+            input_path,
+            output_path,
+        } => {
+            let Some(Model { tokenizer, graph }) = load_model(&graph_path) else {
+                return;
+            };
+            let Some(lines) = read_lines(&input_path) else {
+                return;
+            };
+
+            let mut constructor = graph.into_constructor();
+            register_lines(&mut constructor, tokenizer, &lines);
+
+            println!(
+                "Added {} sequences. Now computing probabilities.",
+                lines.len()
+            );
+            let graph = constructor.construct();
+
+            println!("Done. Writing file.");
+            save_model(
+                output_path.as_ref().unwrap_or(&graph_path),
+                &Model { tokenizer, graph },
+            );
+        }
+
+        Subcommands::Merge {
+            graph_paths,
+            weights,
+            output_path,
+        } => {
+            if weights
+                .as_ref()
+                .is_some_and(|x| x.len() != graph_paths.len())
+            {
+                println!("Expected one weight per graph.");
+                return;
+            }
+
+            let mut models: Vec<Model> = Vec::with_capacity(graph_paths.len());
+            for graph_path in graph_paths.iter() {
+                let Some(model) = load_model(graph_path) else {
+                    return;
+                };
+                models.push(model);
+            }
+
+            let tokenizer = models[0].tokenizer;
+            if models.iter().any(|x| x.tokenizer != tokenizer) {
+                println!("Graphs built with different tokenizers can't be merged.");
+                return;
+            }
+
+            let graphs: Vec<&Graph<Token>> = models.iter().map(|x| &x.graph).collect();
+            let graph = match weights {
+                None => Graph::merge(&graphs),
+                Some(weights) => {
+                    let weighted: Vec<(&Graph<Token>, f64)> =
+                        graphs.into_iter().zip(weights).collect();
+                    Graph::weighted_merge(&weighted)
+                }
+            };
+
+            match graph {
+                Ok(graph) => save_model(&output_path, &Model { tokenizer, graph }),
+                Err(error) => println!("Unable to merge graphs: {error}"),
+            }
+        }
+
+        Subcommands::Top {
+            graph_path,
+            count,
+            greedy,
+            max_tokens,
+        } => {
+            let Some(Model { tokenizer, graph }) = load_model(&graph_path) else {
+                return;
+            };
+
+            let sequences: Vec<ScoredSequence<Token>> = if greedy {
+                graph.most_likely(max_tokens).into_iter().collect()
+            } else {
+                graph.beam_search(count as usize, max_tokens)
+            };
+            if sequences.is_empty() {
+                println!(
+                    "The graph can't generate complete sequences of up to {max_tokens} tokens."
+                );
+                return;
+            }
+
+            for sequence in sequences {
+                println!(
+                    "{:.3}\t{}",
+                    sequence.log_probability,
+                    tokenizer.join(&sequence.values)
+                );
+            }
+        }
+
+        Subcommands::Score {
+            graph_path,
+            input_path,
+            unseen_probability,
+            per_token,
+            breakdown,
+        } => {
+            if unseen_probability.is_some_and(|x| !(x > 0.0 && x <= 1.0)) {
+                println!("The unseen transition probability must be in (0, 1].");
+                return;
+            }
+
+            let Some(Model { tokenizer, graph }) = load_model(&graph_path) else {
+                return;
+            };
+            let Some(lines) = read_lines(&input_path) else {
+                return;
+            };
+
+            let unseen = match unseen_probability {
+                Some(x) => UnseenTransitions::Probability(x),
+                None => UnseenTransitions::Impossible,
+            };
+            for line in lines {
+                let score = graph.score(&tokenizer.tokenize(&line), unseen);
+                let mut log_probability = score.log_probability;
+                if per_token {
+                    log_probability /= score.steps.len() as f64;
+                }
+                println!("{log_probability:.3}\t{line}");
+
+                if breakdown {
+                    for step in score.steps {
+                        let token = match &step.symbol {
+                            Symbol::Value(x) => x.as_str(),
+                            _ => "<end>",
+                        };
+                        let unseen_note = if step.seen { "" } else { " (unseen)" };
+                        println!("\t{:.3}\t{token}{unseen_note}", step.log_probability);
+                    }
+                }
+            }
+        }
+
+        Subcommands::Eval {
+            graph_path,
+            heldout_path,
+            split,
+            order,
+            tokenizer,
+            seed,
+            unseen_probability,
+            smoothing,
+        } => {
+            let Some(smoothing) = parse_smoothing(smoothing) else {
+                return;
+            };
+            if unseen_probability.is_some_and(|x| !(x > 0.0 && x <= 1.0)) {
+                println!("The unseen transition probability must be in (0, 1].");
+                return;
+            }
+            let unseen = match unseen_probability {
+                Some(x) => UnseenTransitions::Probability(x),
+                None => UnseenTransitions::Impossible,
+            };
+
+            let (tokenizer, graph, heldout) = match (heldout_path, split) {
+                (Some(heldout_path), _) => {
+                    let Some(Model { tokenizer, graph }) = load_model(&graph_path) else {
+                        return;
+                    };
+                    let Some(heldout) = read_lines(&heldout_path) else {
+                        return;
+                    };
+
+                    (tokenizer, graph, heldout)
+                }
+                (None, Some(split)) => {
+                    if !(split > 0.0 && split < 1.0) {
+                        println!("The held-out share must be between 0 and 1.");
+                        return;
+                    }
+                    let Some(mut lines) = read_lines(&graph_path) else {
+                        return;
+                    };
+
+                    let mut rng = match seed {
+                        Some(seed) => fastrand::Rng::with_seed(seed),
+                        None => fastrand::Rng::new(),
+                    };
+                    rng.shuffle(&mut lines);
+                    let heldout_count = ((lines.len() as f64 * split).round() as usize).max(1);
+                    let heldout = lines.split_off(lines.len().saturating_sub(heldout_count));
+
+                    let mut constructor = GraphConstructor::with_order(order as usize);
+                    register_lines(&mut constructor, tokenizer, &lines);
+                    println!(
+                        "Compiled graph from {} lines, holding out {}.",
+                        lines.len(),
+                        heldout.len()
+                    );
+
+                    (tokenizer, constructor.construct(), heldout)
+                }
+                // Enforced by clap.
+                (None, None) => return,
+            };
+            if heldout.is_empty() {
+                println!("There are no held-out sequences to evaluate.");
+                return;
+            }
+            let graph = match smoothing {
+                Some(smoothing) => graph.into_constructor().smoothing(smoothing).construct(),
+                None => graph,
+            };
+
+            let vocabulary: HashSet<&Token> = graph.values().collect();
+            let mut transitions = 0;
+            let mut unseen_transitions = 0;
+            let mut tokens = 0;
+            let mut oov_tokens = 0;
+            let mut log_probability_sum = 0.0;
+            for line in heldout.iter() {
+                let sequence = tokenizer.tokenize(line);
+                tokens += sequence.len();
+                oov_tokens += sequence.iter().filter(|x| !vocabulary.contains(x)).count();
+
+                let score = graph.score(&sequence, unseen);
+                transitions += score.steps.len();
+                unseen_transitions += score.steps.iter().filter(|x| !x.seen).count();
+                log_probability_sum += score.log_probability;
+            }
+
+            // Transitions include the one to the end of each sequence.
+            let cross_entropy = -log_probability_sum / std::f64::consts::LN_2 / transitions as f64;
+            println!("Sequences: {}", heldout.len());
+            println!("Transitions: {transitions}");
+            println!("Cross-entropy: {cross_entropy:.4} bits per transition");
+            println!("Perplexity: {:.4}", cross_entropy.exp2());
+            println!(
+                "Out-of-vocabulary rate: {:.2}%",
+                percentage(oov_tokens, tokens)
+            );
+            println!(
+                "Unseen transition rate: {:.2}%",
+                percentage(unseen_transitions, transitions)
+            );
+        }
+
+        Subcommands::Classify {
+            models,
+            input_path,
+            all,
+            smoothing,
+        } => {
+            let Some(smoothing) = parse_smoothing(smoothing) else {
+                return;
+            };
+            let mut classifier = Classifier::new(smoothing.unwrap_or(Smoothing::KneserNey(0.75)));
+
+            // Scores are only comparable if every graph splits lines into the same tokens.
+            let mut classifier_tokenizer: Option<Tokenizer> = None;
+            for (name, path) in models {
+                if classifier.names().any(|x| x == name) {
+                    println!("Model names must be distinct, but {name} is given twice.");
+                    return;
+                }
+
+                let Some(Model { tokenizer, graph }) = load_model(&path) else {
+                    return;
+                };
+                if classifier_tokenizer.is_some_and(|x| x != tokenizer) {
+                    println!("All models must be compiled with the same tokenizer.");
+                    return;
+                }
+                classifier_tokenizer = Some(tokenizer);
+                classifier.add(name, graph);
+            }
+            // Enforced by clap.
+            let Some(tokenizer) = classifier_tokenizer else {
+                return;
+            };
+
+            let Some(lines) = read_lines(&input_path) else {
+                return;
+            };
+            for line in lines {
+                let classifications = classifier.classify(&tokenizer.tokenize(&line));
+                if all {
+                    let posteriors: Vec<String> = classifications
+                        .iter()
+                        .map(|x| format!("{}={:.3}", x.name, x.posterior))
+                        .collect();
+                    println!("{}\t{line}", posteriors.join(" "));
+                } else {
+                    let best = &classifications[0];
+                    println!("{}\t{:.3}\t{line}", best.name, best.posterior);
+                }
+            }
+        }
+
+        Subcommands::Sample {
+            graph_path,
+            count,
+            min_length: min_length_input,
+            max_length,
+            exact_length,
+            prefix,
+            suffix,
+            ban,
+            require,
+            novel,
+            min_edit_distance,
+            temperature,
+            top_k,
+            top_p,
+            backoff,
+            unique,
+            max_duplicates,
+            max_tokens,
+            max_attempts,
+            keep_incomplete,
+            seed,
+        } => {
+            if count == 0 {
+                println!("Sample count can't be 0.");
+                return;
+            }
+
+            let Some(Model { tokenizer, graph }) = load_model(&graph_path) else {
+                return;
+            };
+
+            // Sequences of the exact length would time out otherwise. Stepping gives up once the timeout is reached,
+            // before taking the step to the end of the sequence.
+            let max_tokens =
+                exact_length.map_or(max_tokens, |x| max_tokens.max(x.saturating_add(1)));
+
+            let min_length: Option<usize> = if min_length_input == 0 {
+                None
+            } else {
+                Some(min_length_input)
+            };
+
+            let mut distinct: HashSet<Vec<Token>> = HashSet::new();
+            let required_sequences: Vec<Vec<Token>> = require
+                .iter()
+                .map(|x| tokenizer.tokenize(x))
+                .filter(|x| distinct.insert(x.clone()))
+                .collect();
+            if required_sequences.len() > MAX_REQUIRED_SEQUENCES {
+                println!(
+                    "At most {MAX_REQUIRED_SEQUENCES} distinct --require texts are supported."
+                );
+                return;
+            }
+
+            let graph_reversed = graph.is_reversed();
+            let graph = Arc::new(graph);
+            let configuration = GraphStepperConfiguration {
+                min_length,
+                max_length,
+                exact_length,
+                prefix: prefix.map(|x| tokenizer.tokenize(&x)).unwrap_or_default(),
+                suffix: suffix.map(|x| tokenizer.tokenize(&x)).unwrap_or_default(),
+                banned_values: ban.map(|x| tokenizer.tokenize(&x)).unwrap_or_default(),
+                required_sequences,
+                min_edit_distance: if novel { Some(1) } else { min_edit_distance },
+                temperature,
+                top_k,
+                top_p,
+                backoff_threshold: backoff.map(|x| x as usize),
+                ..Default::default()
+            };
+            let stepper = match seed {
+                Some(seed) => GraphStepper::with_seed(graph, configuration, seed),
+                None => GraphStepper::new(graph, configuration),
+            };
+
+            let mut stepper = match stepper {
+                Ok(x) => x,
+                Err(GraphStepperError::InvalidParameter(InvalidConfigurationParameter::Prefix)) => {
+                    if graph_reversed {
+                        println!("Prefixes can't be used with graphs compiled with --reverse.");
+                    } else {
+                        println!(
+                            "The graph can't generate sequences starting with the given prefix."
+                        );
+                    }
+                    return;
+                }
+                Err(GraphStepperError::InvalidParameter(InvalidConfigurationParameter::Suffix)) => {
+                    if graph_reversed {
+                        println!(
+                            "The graph can't generate sequences ending with the given suffix."
+                        );
+                    } else {
+                        println!("Suffixes require a graph compiled with --reverse.");
+                    }
+                    return;
+                }
+                Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::Constraints,
+                )) => {
+                    println!("The graph can't generate sequences satisfying --ban and --require, or there are too many --require texts for the size of the graph.");
+                    return;
+                }
+                Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::ExactLength,
+                )) => {
+                    println!("The graph can't generate sequences of exactly that length.");
+                    return;
+                }
+                Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::MinEditDistance,
+                )) => {
+                    println!("Rejecting dataset sequences requires a graph compiled with --keep-sequences.");
+                    return;
+                }
+                Err(error) => {
+                    println!("Unable to sample graph: {error}");
+                    return;
+                }
+            };
+
+            // Only used with `unique`.
+            let sequence_count = stepper.sequence_count();
+            let mut seen: HashSet<Vec<Token>> = HashSet::new();
+            let mut consecutive_duplicates = 0;
+
+            let max_attempts = max_attempts.unwrap_or(count.saturating_mul(100));
+            let mut report = SamplingReport::default();
+            let mut iteration = 0;
+            while iteration < count {
+                if report.attempts >= max_attempts {
+                    println!("Generated {iteration} of {count} sequences. Gave up after {report}.");
+                    return;
+                }
+                report.attempts += 1;
+
+                let out = match stepper.step_until_end_state(max_tokens) {
+                    Ok(GraphStepperOut::Reached(out)) => out,
+                    Ok(GraphStepperOut::Timeout(out)) => {
+                        report.timeouts += 1;
+                        if !keep_incomplete {
+                            continue;
+                        }
+                        out
+                    }
+                    Ok(GraphStepperOut::Exhausted(out)) => {
+                        report.exhaustions += 1;
+                        if !keep_incomplete {
+                            continue;
+                        }
+                        out
+                    }
+                    Ok(GraphStepperOut::Rejected(_)) => {
+                        report.rejections += 1;
+                        continue;
+                    }
+                    Err(error) => {
+                        println!("Error during sampling: {error}");
+                        return;
+                    }
+                };
+
+                if unique && seen.contains(&out) {
+                    report.duplicates += 1;
+                    consecutive_duplicates += 1;
+                    if consecutive_duplicates >= max_duplicates {
+                        println!(
+                            "Found {} unique sequences before sampling {max_duplicates} duplicates in a row. The graph likely can't generate more.",
+                            seen.len()
+                        );
+                        return;
+                    }
+                    continue;
+                }
+
+                let _line = tokenizer.join(&out);
+                #[cfg(not(feature = "cli_no_print"))]
+                println!("{_line}");
+                iteration += 1;
+
+                if unique {
+                    consecutive_duplicates = 0;
+                    seen.insert(out);
+
+                    // Incomplete sequences aren't part of the count.
+                    if !keep_incomplete
+                        && iteration < count
+                        && sequence_count == Some(seen.len() as u128)
+                    {
+                        println!(
+                            "Found all {} unique sequences the graph can generate.",
+                            seen.len()
+                        );
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tallies sampling attempts that didn't produce a sequence, reported when sampling gives up.
+#[derive(Default)]
+struct SamplingReport {
+    attempts: usize,
+    timeouts: usize,
+    exhaustions: usize,
+    rejections: usize,
+    duplicates: usize,
+}
+
+impl Display for SamplingReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} attempts ({} hit --max-tokens, {} ran out of edges, {} too close to the dataset, {} duplicates)",
+            self.attempts, self.timeouts, self.exhaustions, self.rejections, self.duplicates
+        )
+    }
+}
+
+/// The smoothing options shared by every subcommand that scores sequences.
+#[derive(Args)]
+struct SmoothingArgs {
+    #[arg(
+        long,
+        value_enum,
+        help = "How scoring assigns probability to transitions that don't occur in the dataset."
+    )]
+    smoothing: Option<SmoothingKind>,
+    #[arg(
+        long,
+        help = "The amount added to each count with additive smoothing (default 1), or the discount of Kneser-Ney smoothing (default 0.75).",
+        requires = "smoothing"
+    )]
+    smoothing_amount: Option<f64>,
+}
+
+/// The smoothing strategies selectable with --smoothing, see [`Smoothing`].
+#[derive(Clone, Copy, ValueEnum)]
+enum SmoothingKind {
+    None,
+    Additive,
+    WittenBell,
+    KneserNey,
+    Katz,
+}
+
+/// Combines --smoothing and --smoothing-amount, reporting invalid amounts to the user. `Some(None)` if no smoothing
+/// was selected.
+fn parse_smoothing(args: SmoothingArgs) -> Option<Option<Smoothing>> {
+    let amount = args.smoothing_amount;
+    let smoothing = match args.smoothing {
+        None => return Some(None),
+        Some(SmoothingKind::None) => Smoothing::None,
+        Some(SmoothingKind::Additive) => Smoothing::Additive(amount.unwrap_or(1.0)),
+        Some(SmoothingKind::WittenBell) => Smoothing::WittenBell,
+        Some(SmoothingKind::KneserNey) => Smoothing::KneserNey(amount.unwrap_or(0.75)),
+        Some(SmoothingKind::Katz) => Smoothing::Katz,
+    };
+
+    match smoothing {
+        Smoothing::Additive(x) if !(x.is_finite() && x > 0.0) => {
+            println!("The additive smoothing amount must be positive.");
+            None
+        }
+        Smoothing::KneserNey(x) if !(x > 0.0 && x <= 1.0) => {
+            println!("The Kneser-Ney discount must be in (0, 1].");
+            None
+        }
+        _ if amount.is_some()
+            && !matches!(smoothing, Smoothing::Additive(_) | Smoothing::KneserNey(_)) =>
+        {
+            println!("--smoothing-amount only applies to additive and Kneser-Ney smoothing.");
+            None
+        }
+        _ => Some(Some(smoothing)),
+    }
+}
+
+/// Parses the NAME=PATH pairs of `markovcli classify --model`.
+fn parse_named_path(input: &str) -> Result<(String, PathBuf), String> {
+    match input.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err("expected NAME=PATH".to_string()),
+    }
+}
+
+fn percentage(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    part as f64 / total as f64 * 100.0
+}
+
+fn register_lines(
+    constructor: &mut GraphConstructor<Token>,
+    tokenizer: Tokenizer,
+    lines: &[String],
+) {
+    for line in lines {
+        constructor.register_whole_sequence(tokenizer.tokenize(line));
+    }
+}
+
+/// Reads all lines of a text file, reporting errors to the user.
+fn read_lines(path: &PathBuf) -> Option<Vec<String>> {
+    let input_file = match OpenOptions::new().read(true).open(path) {
+        Ok(x) => x,
+        Err(error) => {
+            println!("Unable to open input file: {error}");
+            return None;
+        }
+    };
+
+    match BufReader::new(input_file).lines().collect() {
+        Ok(x) => Some(x),
+        Err(error) => {
+            println!("Unable to read line: {error}");
+            None
+        }
+    }
+}
+
+fn load_model(path: &PathBuf) -> Option<Model> {
+    let input_file = match OpenOptions::new().read(true).open(path) {
+        Ok(x) => x,
+        Err(error) => {
+            println!("Unable to open graph file: {error}");
+            return None;
+        }
+    };
+    let input_reader = BufReader::new(input_file);
+
+    match bincode::deserialize_from::<BufReader<File>, Model>(input_reader) {
+        Ok(x) => Some(x),
+        Err(_) => {
+            println!("Unable to parse graph file.");
+            None
+        }
+    }
+}
+
+fn save_model(path: &PathBuf, model: &Model) {
+    let output_file = match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+    {
+        Ok(x) => x,
+        Err(error) => {
+            println!("Unable to open output file: {error}");
+            return;
+        }
+    };
+    let output_writer = BufWriter::new(output_file);
+
+    if let Err(error) = bincode::serialize_into(output_writer, model) {
+        println!("Error while writing: {error}");
+    }
+}
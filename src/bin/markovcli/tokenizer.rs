@@ -0,0 +1,150 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use smartstring::alias::String;
+use unicode_segmentation::UnicodeSegmentation;
+
+pub type Token = String;
+
+/// Punctuation that attaches to the preceding token when joining.
+const CLOSING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '}', '%'];
+/// Punctuation that attaches to the following token when joining.
+const OPENING_PUNCTUATION: &[char] = &['(', '[', '{'];
+/// Quotes alternate between opening and closing ones when joining, attaching to the quoted tokens.
+const QUOTES: &[char] = &['"', '\''];
+
+#[derive(Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+pub enum Tokenizer {
+    /// Every character is a token.
+    Chars,
+    /// Every extended grapheme cluster is a token, keeping combining marks and emoji sequences intact.
+    Graphemes,
+    /// Tokens are separated by whitespace.
+    Words,
+    /// Tokens are separated by whitespace, with punctuation split off into tokens of its own.
+    WordsPunctuation,
+}
+
+impl Tokenizer {
+    pub fn tokenize(&self, line: &str) -> Vec<Token> {
+        match self {
+            Tokenizer::Chars => line.chars().map(char_token).collect(),
+            Tokenizer::Graphemes => line.graphemes(true).map(Token::from).collect(),
+            Tokenizer::Words => line.split_whitespace().map(Token::from).collect(),
+            Tokenizer::WordsPunctuation => {
+                let mut tokens = Vec::new();
+                for word in line.split_whitespace() {
+                    split_punctuation(word, &mut tokens);
+                }
+
+                tokens
+            }
+        }
+    }
+
+    /// Joins tokens produced by [`Tokenizer::tokenize`] back into a line.
+    pub fn join(&self, tokens: &[Token]) -> std::string::String {
+        match self {
+            Tokenizer::Chars | Tokenizer::Graphemes => tokens.concat(),
+            Tokenizer::Words => tokens.join(" "),
+            Tokenizer::WordsPunctuation => {
+                let mut out = std::string::String::new();
+                let mut open_quotes: Vec<&Token> = Vec::new();
+                // Whether the next token attaches to the previous one. The first token has nothing to separate from.
+                let mut attach_next = true;
+                for token in tokens {
+                    let is_quote = is_punctuation(token, QUOTES);
+                    let closes_quote = is_quote && open_quotes.contains(&token);
+
+                    if !(attach_next || closes_quote || is_punctuation(token, CLOSING_PUNCTUATION))
+                    {
+                        out.push(' ');
+                    }
+                    out.push_str(token);
+
+                    if closes_quote {
+                        open_quotes.retain(|x| *x != token);
+                    } else if is_quote {
+                        open_quotes.push(token);
+                    }
+                    attach_next =
+                        is_punctuation(token, OPENING_PUNCTUATION) || (is_quote && !closes_quote);
+                }
+
+                out
+            }
+        }
+    }
+}
+
+/// Splits punctuation off `word`, keeping apostrophes and hyphens that sit between two alphanumeric characters
+/// (e.g. "don't", "well-known").
+fn split_punctuation(word: &str, tokens: &mut Vec<Token>) {
+    let chars: Vec<char> = word.chars().collect();
+    let mut current = Token::new();
+
+    for (index, char) in chars.iter().enumerate() {
+        let is_joiner = (*char == '\'' || *char == '-')
+            && index > 0
+            && chars[index - 1].is_alphanumeric()
+            && chars.get(index + 1).is_some_and(|x| x.is_alphanumeric());
+
+        if char.is_alphanumeric() || is_joiner {
+            current.push(*char);
+            continue;
+        }
+
+        if !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        }
+        tokens.push(char_token(*char));
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+}
+
+fn char_token(char: char) -> Token {
+    Token::from(char.encode_utf8(&mut [0; 4]))
+}
+
+fn is_punctuation(token: &str, set: &[char]) -> bool {
+    let mut chars = token.chars();
+    matches!((chars.next(), chars.next()), (Some(x), None) if set.contains(&x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(tokenizer: Tokenizer, line: &str) {
+        assert_eq!(tokenizer.join(&tokenizer.tokenize(line)), line);
+    }
+
+    #[test]
+    fn words_punctuation_round_trips() {
+        for line in [
+            "He said \"Hello,\" she said.",
+            "\"Yes.\" \"No.\"",
+            "She called it 'the best' (really!) and left.",
+            "\"Don't say 'never',\" he replied; it's well-known.",
+            "Results [see 1, 2] improved by 50%: {a, b}?",
+            "Wait - what?",
+        ] {
+            assert_round_trip(Tokenizer::WordsPunctuation, line);
+        }
+    }
+
+    #[test]
+    fn words_punctuation_splits_quotes() {
+        let tokens = Tokenizer::WordsPunctuation.tokenize("He said \"Hi,\" twice.");
+        assert_eq!(tokens, ["He", "said", "\"", "Hi", ",", "\"", "twice", "."]);
+    }
+
+    #[test]
+    fn other_tokenizers_round_trip() {
+        assert_round_trip(Tokenizer::Chars, "Hello, world!");
+        assert_round_trip(Tokenizer::Graphemes, "nai\u{308}ve \u{1F44B}\u{1F3FD}");
+        assert_round_trip(Tokenizer::Words, "Hello, world!");
+    }
+}
@@ -0,0 +1,105 @@
+use std::hash::Hash;
+
+use crate::{Graph, Smoothing, UnseenTransitions};
+
+/// Guesses which of several named graphs, e.g. one per language or style, a sequence most likely comes from, by
+/// comparing how likely each graph is to generate it. Every graph scores with the same smoothing, so that their
+/// scores are comparable.
+pub struct Classifier<T> {
+    smoothing: Smoothing,
+    classes: Vec<(String, Graph<T>)>,
+}
+
+/// How likely a sequence passed to [`Classifier::classify`] is to come from one of its classes.
+#[derive(Clone, Debug)]
+pub struct Classification {
+    pub name: String,
+    /// Natural logarithm of the probability of the class's graph generating the sequence.
+    pub log_likelihood: f64,
+    /// The probability of the sequence coming from this class rather than any of the others, assuming all classes
+    /// are equally likely beforehand. Sums up to 1 over all classes.
+    pub posterior: f64,
+}
+
+impl<T> Classifier<T> {
+    /// Creates a classifier without any classes, scoring with `smoothing`. Use a smoothing other than
+    /// [`Smoothing::None`], or any transition a graph doesn't contain rules its class out entirely.
+    ///
+    /// # Panics
+    /// Panics if the parameters of `smoothing` are invalid, see [`crate::GraphConstructor::smoothing`].
+    pub fn new(smoothing: Smoothing) -> Self {
+        smoothing.assert_valid();
+
+        Self {
+            smoothing,
+            classes: Vec::new(),
+        }
+    }
+
+    /// Adds a class, replacing any class with the same name. Graphs compiled with a different smoothing are rebuilt
+    /// with the classifier's.
+    pub fn add(&mut self, name: impl Into<String>, graph: Graph<T>)
+    where
+        T: Eq + Hash + Clone,
+    {
+        let name = name.into();
+        let graph = match graph.smoothing() == self.smoothing {
+            true => graph,
+            false => graph
+                .into_constructor()
+                .smoothing(self.smoothing)
+                .construct(),
+        };
+
+        match self.classes.iter_mut().find(|(x, _)| *x == name) {
+            Some(class) => class.1 = graph,
+            None => self.classes.push((name, graph)),
+        }
+    }
+
+    /// The names of all classes, in the order they were added.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.classes.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Scores `sequence` under every class, as a whole sequence like [`Graph::log_probability`]. Classifications
+    /// are sorted by descending posterior, so the most likely class comes first. If no class can generate the
+    /// sequence at all, the posteriors are split evenly.
+    pub fn classify(&self, sequence: &[T]) -> Vec<Classification>
+    where
+        T: Eq + Hash + Clone,
+    {
+        let mut out: Vec<Classification> = self
+            .classes
+            .iter()
+            .map(|(name, graph)| Classification {
+                name: name.clone(),
+                log_likelihood: graph.log_probability(sequence, UnseenTransitions::Impossible),
+                posterior: 0.0,
+            })
+            .collect();
+
+        // Relative to the highest likelihood, so that long sequences don't make every likelihood underflow.
+        let max_log_likelihood = out
+            .iter()
+            .map(|x| x.log_likelihood)
+            .fold(f64::NEG_INFINITY, f64::max);
+        if max_log_likelihood == f64::NEG_INFINITY {
+            for classification in out.iter_mut() {
+                classification.posterior = 1.0 / self.classes.len() as f64;
+            }
+            return out;
+        }
+
+        for classification in out.iter_mut() {
+            classification.posterior = (classification.log_likelihood - max_log_likelihood).exp();
+        }
+        let posterior_sum: f64 = out.iter().map(|x| x.posterior).sum();
+        for classification in out.iter_mut() {
+            classification.posterior /= posterior_sum;
+        }
+
+        out.sort_by(|a, b| b.posterior.total_cmp(&a.posterior));
+        out
+    }
+}
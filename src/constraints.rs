@@ -0,0 +1,384 @@
+use std::collections::VecDeque;
+
+use crate::{Graph, Symbol, Vertex};
+
+/// Tracks progress towards [`crate::GraphStepperConfiguration::required_sequences`] while stepping.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ConstraintState {
+    /// Node of the automaton matching the required sequences.
+    node: usize,
+    /// Bit set of the required sequences generated so far.
+    found: usize,
+}
+
+/// Precomputed for a graph and a set of banned values and required sequences: which states can still reach a
+/// complete sequence that contains every required sequence without generating any banned value, and how likely
+/// they are to do so.
+pub(crate) struct ConstraintTable<T> {
+    banned_values: Vec<T>,
+    automaton: Automaton<T>,
+    /// Indexed by the `found` bit set, and then by `vertex_index * node_count + node`.
+    viable: Vec<Vec<bool>>,
+    /// Probability of completing a sequence satisfying the constraints, indexed like `viable`. Approximated
+    /// iteratively, so it may be 0 for states that are viable after all.
+    success_probabilities: Vec<Vec<f64>>,
+}
+
+/// The most states, i.e. combinations of a vertex, an automaton node and a set of found required sequences, a table
+/// is built for. Each state takes 9 bytes, and approximating the success probabilities visits all of them once per
+/// iteration.
+const MAX_STATES: usize = 1 << 24;
+
+/// Iterations after which approximating the success probabilities is stopped, even if they haven't converged yet.
+const MAX_ITERATIONS: usize = 1000;
+const CONVERGENCE_THRESHOLD: f64 = 1e-9;
+
+impl<T: Eq + Clone> ConstraintTable<T> {
+    /// Sequences are complete once they reach a dead end, or on graphs without dead ends, once every required
+    /// sequence was generated. Memory use grows exponentially with the number of required sequences, so `None` is
+    /// returned if the table would hold more than `MAX_STATES` states.
+    pub(crate) fn new(
+        graph: &Graph<T>,
+        banned_values: &[T],
+        required_sequences: &[Vec<T>],
+    ) -> Option<Self> {
+        // Reversed graphs build sequences back to front.
+        let patterns: Vec<Vec<T>> = required_sequences
+            .iter()
+            .map(|x| match graph.reversed {
+                true => x.iter().rev().cloned().collect(),
+                false => x.clone(),
+            })
+            .collect();
+
+        let automaton = Automaton::new(&patterns);
+        let state_count = 1usize
+            .checked_shl(patterns.len() as u32)?
+            .checked_mul(graph.vertices.len())?
+            .checked_mul(automaton.node_count())?;
+        if state_count > MAX_STATES {
+            return None;
+        }
+
+        let mut table = Self {
+            banned_values: banned_values.to_vec(),
+            automaton,
+            viable: Vec::new(),
+            success_probabilities: Vec::new(),
+        };
+        table.update_viable(graph);
+
+        Some(table)
+    }
+
+    /// The state before any value was generated.
+    pub(crate) fn initial_state(&self) -> ConstraintState {
+        ConstraintState {
+            node: 0,
+            found: self.automaton.found[0],
+        }
+    }
+
+    pub(crate) fn advance(&self, state: ConstraintState, value: &T) -> ConstraintState {
+        let node = self.automaton.next(state.node, value);
+        ConstraintState {
+            node,
+            found: state.found | self.automaton.found[node],
+        }
+    }
+
+    pub(crate) fn is_banned(&self, value: &T) -> bool {
+        self.banned_values.contains(value)
+    }
+
+    /// Whether the constraints can still be satisfied from the vertex with index `vertex_index`.
+    pub(crate) fn is_viable(&self, vertex_index: usize, state: ConstraintState) -> bool {
+        self.viable[state.found][vertex_index * self.automaton.node_count() + state.node]
+    }
+
+    /// Whether stepping onto the vertex with index `target` keeps the constraints satisfiable.
+    pub(crate) fn allows(&self, graph: &Graph<T>, state: ConstraintState, target: usize) -> bool {
+        self.enter(graph, state, target)
+            .is_some_and(|x| self.is_viable(target, x))
+    }
+
+    /// The probability of completing a sequence satisfying the constraints after stepping onto the vertex with
+    /// index `target`.
+    pub(crate) fn success_probability(
+        &self,
+        graph: &Graph<T>,
+        state: ConstraintState,
+        target: usize,
+    ) -> f64 {
+        match self.enter(graph, state, target) {
+            Some(x) => {
+                self.success_probabilities[x.found][target * self.automaton.node_count() + x.node]
+            }
+            None => 0.0,
+        }
+    }
+
+    /// The state after stepping onto the vertex with index `target`, or `None` if it emits a banned value.
+    fn enter(
+        &self,
+        graph: &Graph<T>,
+        state: ConstraintState,
+        target: usize,
+    ) -> Option<ConstraintState> {
+        match graph.vertices[target].value() {
+            Symbol::Value(value) if self.is_banned(value) => None,
+            Symbol::Value(value) => Some(self.advance(state, value)),
+            _ => Some(state),
+        }
+    }
+
+    /// Works through the `found` bit sets from the largest down, since generating values can only add to them. Within
+    /// each, viable states are found by a backwards search from the states that complete the sequence or move on to
+    /// a viable larger set, and their success probabilities are approximated by repeatedly averaging over their
+    /// edges.
+    fn update_viable(&mut self, graph: &Graph<T>) {
+        let node_count = self.automaton.node_count();
+        let complete = (1 << self.automaton.pattern_count) - 1;
+        let has_dead_ends = graph.vertices.iter().any(|x| x.edges.is_empty());
+
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); graph.vertices.len()];
+        for (index, vertex) in graph.vertices.iter().enumerate() {
+            for edge in vertex.edges.iter() {
+                predecessors[edge.vertex_index].push(index);
+            }
+        }
+
+        // The node reached when stepping onto each vertex from each node, `None` for banned values.
+        let mut transitions: Vec<Option<usize>> =
+            Vec::with_capacity(graph.vertices.len() * node_count);
+        for vertex in graph.vertices.iter() {
+            for node in 0..node_count {
+                transitions.push(match vertex.value() {
+                    Symbol::Value(value) if self.is_banned(value) => None,
+                    Symbol::Value(value) => Some(self.automaton.next(node, value)),
+                    // Entering a vertex without a value doesn't advance the automaton.
+                    _ => Some(node),
+                });
+            }
+        }
+
+        self.viable = vec![Vec::new(); complete + 1];
+        self.success_probabilities = vec![Vec::new(); complete + 1];
+        for found in (0..=complete).rev() {
+            let is_goal = |vertex: &Vertex<T>| {
+                found == complete && (vertex.edges.is_empty() || !has_dead_ends)
+            };
+
+            let mut viable = vec![false; graph.vertices.len() * node_count];
+            let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+
+            for (vertex_index, vertex) in graph.vertices.iter().enumerate() {
+                for node in 0..node_count {
+                    let is_seed = is_goal(vertex)
+                        || vertex.edges.iter().any(|edge| {
+                            let Some(next) = transitions[edge.vertex_index * node_count + node]
+                            else {
+                                return false;
+                            };
+                            let next_found = found | self.automaton.found[next];
+                            next_found != found
+                                && self.viable[next_found][edge.vertex_index * node_count + next]
+                        });
+
+                    if is_seed {
+                        viable[vertex_index * node_count + node] = true;
+                        queue.push_back((vertex_index, node));
+                    }
+                }
+            }
+
+            while let Some((target, target_node)) = queue.pop_front() {
+                if found | self.automaton.found[target_node] != found {
+                    continue;
+                }
+
+                for predecessor in predecessors[target].iter() {
+                    for node in 0..node_count {
+                        let index = predecessor * node_count + node;
+                        if transitions[target * node_count + node] == Some(target_node)
+                            && !viable[index]
+                        {
+                            viable[index] = true;
+                            queue.push_back((*predecessor, node));
+                        }
+                    }
+                }
+            }
+
+            let mut probabilities = vec![0.0; graph.vertices.len() * node_count];
+            for _ in 0..MAX_ITERATIONS {
+                let mut change: f64 = 0.0;
+
+                for (vertex_index, vertex) in graph.vertices.iter().enumerate() {
+                    for node in 0..node_count {
+                        let index = vertex_index * node_count + node;
+                        if !viable[index] {
+                            continue;
+                        }
+
+                        let probability = if is_goal(vertex) {
+                            1.0
+                        } else {
+                            vertex
+                                .edges
+                                .iter()
+                                .map(|edge| {
+                                    let target = edge.vertex_index * node_count;
+                                    let Some(next) = transitions[target + node] else {
+                                        return 0.0;
+                                    };
+
+                                    let next_found = found | self.automaton.found[next];
+                                    let success_probability = if next_found == found {
+                                        probabilities[target + next]
+                                    } else {
+                                        self.success_probabilities[next_found][target + next]
+                                    };
+                                    edge.probability as f64 * success_probability
+                                })
+                                .sum()
+                        };
+
+                        change = change.max((probability - probabilities[index]).abs());
+                        probabilities[index] = probability;
+                    }
+                }
+
+                if change < CONVERGENCE_THRESHOLD {
+                    break;
+                }
+            }
+
+            self.viable[found] = viable;
+            self.success_probabilities[found] = probabilities;
+        }
+    }
+}
+
+/// Aho-Corasick automaton, finding all required sequences in a single pass over the generated values.
+struct Automaton<T> {
+    /// Outgoing transitions of each node, the root being node 0.
+    children: Vec<Vec<(T, usize)>>,
+    /// The node for the longest proper suffix of each node's path that is also a path from the root.
+    fallbacks: Vec<usize>,
+    /// Bit set of the patterns ending at each node, including those ending at its fallbacks.
+    found: Vec<usize>,
+    pattern_count: usize,
+}
+
+impl<T: Eq + Clone> Automaton<T> {
+    fn new(patterns: &[Vec<T>]) -> Self {
+        let mut out = Self {
+            children: vec![Vec::new()],
+            fallbacks: vec![0],
+            found: vec![0],
+            pattern_count: patterns.len(),
+        };
+
+        for (pattern_index, pattern) in patterns.iter().enumerate() {
+            let mut node = 0;
+            for value in pattern {
+                node = match out.child(node, value) {
+                    Some(x) => x,
+                    None => {
+                        let child = out.children.len();
+                        out.children.push(Vec::new());
+                        out.fallbacks.push(0);
+                        out.found.push(0);
+                        out.children[node].push((value.clone(), child));
+                        child
+                    }
+                };
+            }
+            out.found[node] |= 1 << pattern_index;
+        }
+
+        // Fallbacks are shorter, so a breadth first traversal computes them before they're needed.
+        let mut queue: VecDeque<usize> = out.children[0].iter().map(|x| x.1).collect();
+        while let Some(node) = queue.pop_front() {
+            for (value, child) in out.children[node].clone() {
+                let fallback = out.next(out.fallbacks[node], &value);
+                out.fallbacks[child] = fallback;
+                out.found[child] |= out.found[fallback];
+                queue.push_back(child);
+            }
+        }
+
+        out
+    }
+
+    fn node_count(&self) -> usize {
+        self.children.len()
+    }
+
+    fn child(&self, node: usize, value: &T) -> Option<usize> {
+        self.children[node]
+            .iter()
+            .find(|(x, _)| x == value)
+            .map(|(_, child)| *child)
+    }
+
+    fn next(&self, mut node: usize, value: &T) -> usize {
+        loop {
+            if let Some(child) = self.child(node, value) {
+                return child;
+            }
+            if node == 0 {
+                return 0;
+            }
+            node = self.fallbacks[node];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `found` bit set after feeding each value of `values` to the automaton.
+    fn found_sets(automaton: &Automaton<char>, values: &str) -> Vec<usize> {
+        let mut node = 0;
+        values
+            .chars()
+            .map(|x| {
+                node = automaton.next(node, &x);
+                automaton.found[node]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn automaton_finds_overlapping_patterns() {
+        let patterns: Vec<Vec<char>> = ["ana", "nan"].iter().map(|x| x.chars().collect()).collect();
+        let automaton = Automaton::new(&patterns);
+
+        // "nan" is only found by falling back from the node of "ana" to that of "na".
+        assert_eq!(
+            found_sets(&automaton, "banana"),
+            [0, 0, 0, 0b01, 0b10, 0b01]
+        );
+        assert_eq!(
+            found_sets(&automaton, "nanana"),
+            [0, 0, 0b10, 0b01, 0b10, 0b01]
+        );
+    }
+
+    #[test]
+    fn automaton_finds_patterns_within_patterns() {
+        let patterns: Vec<Vec<char>> = ["banana", "nan", "a"]
+            .iter()
+            .map(|x| x.chars().collect())
+            .collect();
+        let automaton = Automaton::new(&patterns);
+
+        assert_eq!(
+            found_sets(&automaton, "banana"),
+            [0, 0b100, 0, 0b100, 0b010, 0b101]
+        );
+    }
+}
@@ -8,377 +8,2402 @@ The above copyright notice and this permission notice shall be included in all c
 THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 */
 
-use smartstring::alias::String;
-use std::{collections::HashMap, error::Error, fmt::Display, sync::Arc};
+use alias::AliasTable;
+use backoff::BackoffTable;
+use constraints::{ConstraintState, ConstraintTable};
+use smoothing::SmoothingModel;
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet, VecDeque},
+    error::Error,
+    fmt::Display,
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+
+mod alias;
+mod backoff;
+mod classifier;
+mod constraints;
+mod smoothing;
+
+pub use classifier::{Classification, Classifier};
+pub use smoothing::Smoothing;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+/// An element of a state's context: either a value, or one of the markers framing each sequence registered with
+/// [`GraphConstructor::register_whole_sequence`]. In a reversed graph, sequences are read right-to-left, so
+/// [`Symbol::Start`] pads the context after the last value and [`Symbol::End`] precedes the first one.
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-struct Vertex {
-    value: char,
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Symbol<T> {
+    /// Pads the context before the first value of a sequence.
+    Start,
+    Value(T),
+    /// Follows the last value of a sequence. States ending with it have no outgoing edges.
+    End,
+}
+
+/// Everything but the context and edges is derived from the edges by [`Graph::update_probabilities`], and is
+/// recomputed when deserializing instead of being stored.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Vertex<T> {
+    /// The last `order` symbols of the sequence. The final symbol is the one emitted when stepping onto this vertex.
+    context: Vec<Symbol<T>>,
     edges: Vec<Edge>,
+    /// Samples an index into `edges` according to the edge probabilities.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sampler: AliasTable,
+    /// Like `sampler`, but never picks edges leading to dead ends. The remaining edges keep their relative
+    /// probabilities, like with `GraphStepper::avoid_dead_ends`. `None` if that wouldn't change anything or no
+    /// other edges are left.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    min_length_sampler: Option<AliasTable>,
+    /// The least number of values emitted on any path from this vertex to a dead end. `usize::MAX` if no dead end
+    /// can be reached.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    end_distance: usize,
+    /// The largest number of values emitted on the shortest path to a dead end after taking any one of the edges.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    max_edge_end_distance: usize,
+}
+
+impl<T> Vertex<T> {
+    fn value(&self) -> &Symbol<T> {
+        self.context.last().unwrap() // Contexts always hold `order` >= 1 symbols
+    }
+
+    /// Whether stepping onto this vertex adds a value to the built sequence.
+    fn emits_value(&self) -> bool {
+        matches!(self.value(), Symbol::Value(_))
+    }
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone)]
 struct Edge {
     vertex_index: usize,
+    /// How often this transition was registered. Kept so that graphs can be merged, extended or rescored.
+    count: usize,
+    /// Derived from `count` and the counts of the other edges of the same vertex.
     probability: f32,
 }
 
-struct ProtoVertex {
-    value: char,
-    char_ref_counts: HashMap<char, usize>,
+struct ProtoVertex<T> {
+    context: Vec<Symbol<T>>,
+    /// Outgoing transitions, keyed by the symbol that is appended to the context.
+    edges: HashMap<Symbol<T>, ProtoEdge>,
 }
 
-pub struct GraphConstructor {
-    vertices: Vec<ProtoVertex>,
+struct ProtoEdge {
+    vertex_index: usize,
+    count: usize,
+}
+
+/// Collects transitions between values of type `T` (e.g. chars, words or syllables) and builds a [`Graph`] from them.
+pub struct GraphConstructor<T> {
+    order: usize,
+    /// Whether sequences are read right-to-left.
+    reversed: bool,
+    /// Whole sequences registered so far in registration order, if they are kept. Duplicates are only removed when
+    /// constructing the graph.
+    sequences: Option<Vec<Vec<T>>>,
+    smoothing: Smoothing,
+    vertices: Vec<ProtoVertex<T>>,
+    /// Maps each context to its index in `vertices`, so registering a transition doesn't require a linear scan.
+    vertex_indices: HashMap<Vec<Symbol<T>>, usize>,
 }
 
-impl From<GraphConstructor> for Graph {
-    fn from(constructor: GraphConstructor) -> Self {
+impl<T: Eq + Hash + Clone> From<GraphConstructor<T>> for Graph<T> {
+    fn from(constructor: GraphConstructor<T>) -> Self {
         let mut constructed_graph = Graph {
-            vertices: Vec::new(),
+            order: constructor.order,
+            reversed: constructor.reversed,
+            sequences: constructor.sequences.map(deduplicate),
+            smoothing: constructor.smoothing,
+            smoothing_model: None,
+            vertices: Vec::with_capacity(constructor.vertices.len()),
+            vertex_indices: constructor.vertex_indices,
         };
 
-        let mut discovered_dead_ends: Vec<Vertex> = Vec::new();
+        for proto in constructor.vertices {
+            constructed_graph.vertices.push(Vertex {
+                context: proto.context,
+                sampler: AliasTable::new(&[]),
+                min_length_sampler: None,
+                end_distance: usize::MAX,
+                max_edge_end_distance: usize::MAX,
+                edges: proto
+                    .edges
+                    .values()
+                    .map(|x| Edge {
+                        vertex_index: x.vertex_index,
+                        count: x.count,
+                        probability: 0.0,
+                    })
+                    .collect(),
+            });
+        }
 
-        for (proto_index, proto) in constructor.vertices.iter().enumerate() {
-            let mut vertex = Vertex {
-                value: proto.value,
-                edges: Vec::new(),
-            };
+        constructed_graph.update_probabilities();
+        constructed_graph.update_smoothing_model();
+        constructed_graph
+    }
+}
 
-            let ref_counts_sum: usize = proto.char_ref_counts.values().sum();
-            for edge_ref in proto.char_ref_counts.iter() {
-                let mut ref_index: Option<usize> = None;
-                for (other_proto_index, other_proto) in constructor.vertices.iter().enumerate() {
-                    if other_proto.value == *edge_ref.0 {
-                        ref_index = Some(other_proto_index);
-                        break;
-                    }
-                }
+impl<T: Eq + Hash + Clone> From<Graph<T>> for GraphConstructor<T> {
+    fn from(graph: Graph<T>) -> Self {
+        let mut constructor = GraphConstructor::with_order(graph.order);
+        constructor.reversed = graph.reversed;
+        constructor.sequences = graph.sequences;
+        constructor.smoothing = graph.smoothing;
+
+        constructor.vertex_indices = graph.vertex_indices;
+
+        let values: Vec<Symbol<T>> = graph.vertices.iter().map(|x| x.value().clone()).collect();
+        for vertex in graph.vertices {
+            constructor.vertices.push(ProtoVertex {
+                context: vertex.context,
+                edges: vertex
+                    .edges
+                    .iter()
+                    .map(|x| {
+                        (
+                            values[x.vertex_index].clone(),
+                            ProtoEdge {
+                                vertex_index: x.vertex_index,
+                                count: x.count,
+                            },
+                        )
+                    })
+                    .collect(),
+            });
+        }
 
-                if ref_index.is_none() {
-                    for (index, dead_end) in discovered_dead_ends.iter().enumerate() {
-                        if dead_end.value == *edge_ref.0 {
-                            ref_index = Some(constructor.vertices.len() + index);
-                        }
-                    }
-                }
+        constructor
+    }
+}
 
-                if ref_index.is_none() {
-                    // println!(
-                    //     "Unable to find ref_index for value '{}' (0x{:x}). Creating new vertex.",
-                    //     *edge_ref.0, *edge_ref.0 as i32,
-                    // );
-                    let new_vertex = Vertex {
-                        value: *edge_ref.0,
-                        edges: Vec::new(),
-                    };
-                    discovered_dead_ends.push(new_vertex);
+impl<T: Eq + Hash + Clone> Default for GraphConstructor<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-                    ref_index = Some(constructor.vertices.len() + discovered_dead_ends.len() - 1);
-                }
+impl<T: Eq + Hash + Clone> GraphConstructor<T> {
+    /// Creates a constructor for a first-order chain, where each state is a single value.
+    pub fn new() -> Self {
+        Self::with_order(1)
+    }
 
-                let ref_index = ref_index.unwrap();
-                vertex.edges.push(Edge {
-                    vertex_index: ref_index,
-                    probability: *edge_ref.1 as f32 / ref_counts_sum as f32,
-                });
-            }
+    /// Creates a constructor for a chain of the given order, where each state is made up of the last `order` values.
+    ///
+    /// # Panics
+    /// Panics if `order` is 0.
+    pub fn with_order(order: usize) -> Self {
+        assert!(order > 0, "Markov chain order must be at least 1");
 
-            vertex
-                .edges
-                .sort_by(|a, b| a.probability.partial_cmp(&b.probability).unwrap());
-            constructed_graph.vertices.insert(proto_index, vertex);
+        Self {
+            order,
+            reversed: false,
+            sequences: None,
+            smoothing: Smoothing::None,
+            vertices: Vec::new(),
+            vertex_indices: HashMap::new(),
         }
-        for dead_end_vertex in discovered_dead_ends {
-            constructed_graph.vertices.push(dead_end_vertex);
+    }
+
+    /// Makes the constructor read sequences right-to-left, so that the resulting graph generates sequences
+    /// backwards, starting at their end. Steppers on such a graph can guarantee a
+    /// [`GraphStepperConfiguration::suffix`]. Registered sequences are passed in their usual reading order.
+    pub fn reversed(mut self) -> Self {
+        self.reversed = true;
+        self
+    }
+
+    /// Makes the constructor keep every sequence registered with [`GraphConstructor::register_whole_sequence`] in
+    /// the graph, so that steppers can reject reproductions of them using
+    /// [`GraphStepperConfiguration::min_edit_distance`]. Increases the graph size by about the size of the dataset.
+    pub fn keep_sequences(mut self) -> Self {
+        if self.sequences.is_none() {
+            self.sequences = Some(Vec::new());
         }
+        self
+    }
 
-        constructed_graph
+    /// Sets how [`Graph::score`] assigns probability to transitions that don't occur in the dataset. Smoothing
+    /// only affects scoring, steppers still only take observed transitions.
+    ///
+    /// # Panics
+    /// Panics if the amount of [`Smoothing::Additive`] isn't positive, or the discount of [`Smoothing::KneserNey`]
+    /// isn't in `(0, 1]`.
+    pub fn smoothing(mut self, smoothing: Smoothing) -> Self {
+        smoothing.assert_valid();
+
+        self.smoothing = smoothing;
+        self
     }
-}
 
-impl GraphConstructor {
-    pub fn new() -> Self {
-        Self {
-            vertices: Vec::new(),
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.reversed
+    }
+
+    /// Registers a whole sequence, framed by [`Symbol::Start`] and [`Symbol::End`], so that steppers can start at
+    /// the beginning of a sequence and know where sequences end.
+    pub fn register_whole_sequence<I: IntoIterator<Item = T>>(&mut self, sequence: I) {
+        if !self.reversed && self.sequences.is_none() {
+            self.register_framed_values(sequence);
+            return;
+        }
+
+        let mut values: Vec<T> = sequence.into_iter().collect();
+        if let Some(sequences) = &mut self.sequences {
+            sequences.push(values.clone());
+        }
+        if self.reversed {
+            values.reverse();
         }
+        self.register_framed_values(values);
     }
 
-    pub fn register_sequence(&mut self, current: char, next: char) {
-        for vertex in self.vertices.iter_mut() {
-            if vertex.value == current {
-                match vertex.char_ref_counts.get_mut(&next) {
-                    Some(c) => {
-                        *c += 1;
-                    }
-                    None => {
-                        vertex.char_ref_counts.insert(next, 1);
-                    }
-                }
+    fn register_framed_values<I: IntoIterator<Item = T>>(&mut self, values: I) {
+        let mut context = vec![Symbol::Start; self.order];
 
-                return;
-            }
+        for value in values {
+            let next = Symbol::Value(value);
+            self.register_symbol_count(&context, next.clone(), 1);
+            context.remove(0);
+            context.push(next);
         }
 
-        let mut new_vertex = ProtoVertex {
-            value: current,
-            char_ref_counts: HashMap::new(),
+        self.register_symbol_count(&context, Symbol::End, 1);
+    }
+
+    /// Registers a transition from the state made up of the last `order` values of `context` to `next`.
+    /// In a reversed constructor, `context` holds the values following `next` instead, and the first `order` of
+    /// them make up the state.
+    ///
+    /// # Panics
+    /// Panics if `context` holds fewer than `order` values.
+    pub fn register_sequence(&mut self, context: &[T], next: T) {
+        self.register_count(context, next, 1);
+    }
+
+    /// Like [`GraphConstructor::register_sequence`], but registers the transition `count` times at once.
+    ///
+    /// # Panics
+    /// Panics if `context` holds fewer than `order` values.
+    pub fn register_count(&mut self, context: &[T], next: T, count: usize) {
+        assert!(
+            context.len() >= self.order,
+            "Context must hold at least {} values",
+            self.order
+        );
+        let context: Vec<Symbol<T>> = if self.reversed {
+            context[..self.order]
+                .iter()
+                .rev()
+                .cloned()
+                .map(Symbol::Value)
+                .collect()
+        } else {
+            context[context.len() - self.order..]
+                .iter()
+                .cloned()
+                .map(Symbol::Value)
+                .collect()
         };
-        new_vertex.char_ref_counts.insert(next, 1);
 
-        self.vertices.push(new_vertex);
+        self.register_symbol_count(&context, Symbol::Value(next), count);
     }
 
-    pub fn construct(self) -> Graph {
+    /// `context` must hold exactly `order` symbols.
+    fn register_symbol_count(&mut self, context: &[Symbol<T>], next: Symbol<T>, count: usize) {
+        let proto_index = self.find_or_insert(context);
+        if let Some(edge) = self.vertices[proto_index].edges.get_mut(&next) {
+            edge.count += count;
+            return;
+        }
+
+        // Stepping along an edge shifts the context window by one value. The target is registered right away, so
+        // vertex indices only depend on the order sequences were registered in.
+        let mut target_context = context[1..].to_vec();
+        target_context.push(next.clone());
+        let target_index = self.find_or_insert(&target_context);
+
+        self.vertices[proto_index].edges.insert(
+            next,
+            ProtoEdge {
+                vertex_index: target_index,
+                count,
+            },
+        );
+    }
+
+    fn find_or_insert(&mut self, context: &[Symbol<T>]) -> usize {
+        if let Some(index) = self.vertex_indices.get(context) {
+            return *index;
+        }
+
+        let index = self.vertices.len();
+        self.vertices.push(ProtoVertex {
+            context: context.to_vec(),
+            edges: HashMap::new(),
+        });
+        self.vertex_indices.insert(context.to_vec(), index);
+
+        index
+    }
+
+    pub fn construct(self) -> Graph<T> {
         self.into()
     }
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct Graph {
-    vertices: Vec<Vertex>,
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        from = "SerializedGraph<T>",
+        bound(deserialize = "T: Deserialize<'de> + Eq + Hash + Clone")
+    )
+)]
+pub struct Graph<T> {
+    order: usize,
+    /// Whether transitions were read right-to-left, see [`GraphConstructor::reversed`].
+    reversed: bool,
+    /// The distinct training sequences in reading order, listed in the order they were first registered, see
+    /// [`GraphConstructor::keep_sequences`].
+    sequences: Option<Vec<Vec<T>>>,
+    smoothing: Smoothing,
+    /// Precomputed statistics for the smoothing, `None` without smoothing. Rebuilt after deserializing.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    smoothing_model: Option<SmoothingModel<T>>,
+    vertices: Vec<Vertex<T>>,
+    /// Maps each context to its index in `vertices`, like [`GraphConstructor`] does. Rebuilt after deserializing.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    vertex_indices: HashMap<Vec<Symbol<T>>, usize>,
 }
 
-#[derive(Clone)]
-pub struct GraphStepper {
-    graph: Arc<Graph>,
-    position: usize,
-    built_string: String,
-    configuration: GraphStepperConfiguration,
+/// The fields of [`Graph`] that are stored when serializing it, in the same order. Everything else is derived from
+/// them after deserializing.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SerializedGraph<T> {
+    order: usize,
+    reversed: bool,
+    sequences: Option<Vec<Vec<T>>>,
+    smoothing: Smoothing,
+    vertices: Vec<Vertex<T>>,
 }
 
-#[derive(Clone)]
-#[allow(non_snake_case)]
-pub struct GraphStepperConfiguration {
-    pub start_char: Option<char>,
-    pub min_length: Option<usize>,
-}
+#[cfg(feature = "serde")]
+impl<T: Eq + Hash + Clone> From<SerializedGraph<T>> for Graph<T> {
+    fn from(serialized: SerializedGraph<T>) -> Self {
+        let vertex_indices = serialized
+            .vertices
+            .iter()
+            .enumerate()
+            .map(|(index, vertex)| (vertex.context.clone(), index))
+            .collect();
+        let mut graph = Graph {
+            order: serialized.order,
+            reversed: serialized.reversed,
+            sequences: serialized.sequences,
+            smoothing: serialized.smoothing,
+            smoothing_model: None,
+            vertices: serialized.vertices,
+            vertex_indices,
+        };
 
-#[derive(Debug)]
-pub enum GraphStepperError {
-    InvalidParameter(InvalidConfigurationParameter),
-    EdgeExhaustion,
+        graph.update_probabilities();
+        graph.update_smoothing_model();
+        graph
+    }
 }
 
-impl Display for GraphStepperError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let error_message: std::string::String = match self {
-            GraphStepperError::EdgeExhaustion => {
-                "No edges left to create configuration-compliant sample".to_string()
-            }
-            GraphStepperError::InvalidParameter(param) => {
-                let param_name = match param {
-                    InvalidConfigurationParameter::EndChar => "End Char",
-                    InvalidConfigurationParameter::StartChar => "Start Char",
-                };
-                format!("Invalid parameter provided: {param_name}")
-            }
-        };
+impl<T> Graph<T> {
+    /// The number of values making up each state of the chain.
+    pub fn order(&self) -> usize {
+        self.order
+    }
 
-        f.write_str(&error_message)
+    /// Whether the graph generates sequences backwards, see [`GraphConstructor::reversed`].
+    pub fn is_reversed(&self) -> bool {
+        self.reversed
     }
-}
 
-impl Error for GraphStepperError {}
+    /// The sequences the graph was built from, if it was built with [`GraphConstructor::keep_sequences`].
+    pub fn sequences(&self) -> Option<&[Vec<T>]> {
+        self.sequences.as_deref()
+    }
 
-#[derive(Debug)]
-pub enum InvalidConfigurationParameter {
-    StartChar,
-    EndChar,
-}
+    /// How [`Graph::score`] treats transitions that don't occur in the dataset, see
+    /// [`GraphConstructor::smoothing`].
+    pub fn smoothing(&self) -> Smoothing {
+        self.smoothing
+    }
 
-impl GraphStepper {
-    pub fn new(
-        graph: Arc<Graph>,
-        configuration: GraphStepperConfiguration,
-    ) -> Result<Self, GraphStepperError> {
-        let mut out = Self {
-            graph,
-            position: 0,
-            built_string: String::new(),
-            configuration,
-        };
+    /// Every value the graph can generate, i.e. its vocabulary. Values may be repeated once for each context they
+    /// appear at the end of, in chains of an order above 1.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.vertices.iter().filter_map(|x| match x.value() {
+            Symbol::Value(value) => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Turns the graph back into a constructor holding the same transition counts, so more sequences can be
+    /// registered without starting over.
+    pub fn into_constructor(self) -> GraphConstructor<T>
+    where
+        T: Eq + Hash + Clone,
+    {
+        self.into()
+    }
+
+    /// Combines graphs by summing their transition counts, which is equivalent to building a single graph from all
+    /// of their datasets. Vertices are unified by their context. Training sequences are kept if every graph kept
+    /// them, and the smoothing of the first graph is used.
+    pub fn merge(graphs: &[&Graph<T>]) -> Result<Graph<T>, GraphMergeError>
+    where
+        T: Eq + Hash + Clone,
+    {
+        let scaled: Vec<(&Graph<T>, f64)> = graphs.iter().map(|x| (*x, 1.0)).collect();
+        Self::merge_scaled(&scaled)
+    }
 
-        if out.configuration.start_char.is_some()
-            && out
-                .find_position(out.configuration.start_char.unwrap())
-                .is_none()
+    /// Combines graphs like [`Graph::merge`], but rescales each graph's transition counts so that its share of the
+    /// total is proportional to its weight, regardless of how large its dataset was. E.g. weights of `0.7` and `0.3`
+    /// produce a model that is 70% made up of the first graph. Weights don't need to add up to 1.
+    ///
+    /// Counts are scaled up so that the least weighted graph keeps its counts as they are, and then rounded to
+    /// integers, so every transition present in a source with a positive weight is kept. Graphs with a weight of 0,
+    /// or without any transitions, contribute nothing, including their training sequences.
+    pub fn weighted_merge(graphs: &[(&Graph<T>, f64)]) -> Result<Graph<T>, GraphMergeError>
+    where
+        T: Eq + Hash + Clone,
+    {
+        if graphs
+            .iter()
+            .any(|(_, weight)| !weight.is_finite() || *weight < 0.0)
         {
-            return Err(GraphStepperError::InvalidParameter(
-                InvalidConfigurationParameter::StartChar,
-            ));
+            return Err(GraphMergeError::InvalidWeights);
         }
 
-        out.reset_position();
-        Ok(out)
+        let weight_sum: f64 = graphs.iter().map(|(_, weight)| *weight).sum();
+        if weight_sum <= 0.0 {
+            return Err(GraphMergeError::InvalidWeights);
+        }
+
+        let totals: Vec<usize> = graphs
+            .iter()
+            .map(|(graph, _)| graph.total_count())
+            .collect();
+        let total: usize = totals.iter().sum();
+
+        let mut scaled: Vec<(&Graph<T>, f64)> = graphs
+            .iter()
+            .zip(totals)
+            .map(|((graph, weight), graph_total)| {
+                if graph_total == 0 {
+                    (*graph, 0.0)
+                } else {
+                    (
+                        *graph,
+                        weight / weight_sum * total as f64 / graph_total as f64,
+                    )
+                }
+            })
+            .collect();
+
+        // Scaling counts below 1 would round many of them to the same value, skewing the shares.
+        let min_scale = scaled
+            .iter()
+            .map(|(_, scale)| *scale)
+            .filter(|x| *x > 0.0)
+            .fold(f64::INFINITY, f64::min);
+        if min_scale.is_finite() {
+            for (_, scale) in scaled.iter_mut() {
+                *scale /= min_scale;
+            }
+        }
+
+        Self::merge_scaled(&scaled)
     }
 
-    fn reset_position(&mut self) {
-        match self.configuration.start_char {
-            Some(x) => self.position = self.find_position(x).unwrap(), // Checked in constructor
-            None => self.position = self.random_position(),
+    fn merge_scaled(graphs: &[(&Graph<T>, f64)]) -> Result<Graph<T>, GraphMergeError>
+    where
+        T: Eq + Hash + Clone,
+    {
+        let (order, reversed, smoothing) = match graphs.first() {
+            Some((graph, _)) => (graph.order, graph.reversed, graph.smoothing),
+            None => return Err(GraphMergeError::NoGraphs),
+        };
+        if graphs.iter().any(|(graph, _)| graph.order != order) {
+            return Err(GraphMergeError::OrderMismatch);
+        }
+        if graphs.iter().any(|(graph, _)| graph.reversed != reversed) {
+            return Err(GraphMergeError::DirectionMismatch);
+        }
+
+        let mut constructor = GraphConstructor::with_order(order);
+        constructor.reversed = reversed;
+        constructor.smoothing = smoothing;
+        // Training sequences are only kept if they are known for every graph contributing any counts, so that
+        // graphs with a weight of 0 don't make steppers reject sequences the merged graph never learned from.
+        let contributing = graphs.iter().filter(|(_, scale)| *scale > 0.0);
+        if contributing
+            .clone()
+            .all(|(graph, _)| graph.sequences.is_some())
+        {
+            constructor.sequences = Some(
+                contributing
+                    .flat_map(|(graph, _)| graph.sequences.iter().flatten().cloned())
+                    .collect(),
+            );
+        }
+        for (graph, scale) in graphs {
+            if *scale <= 0.0 {
+                continue;
+            }
+
+            for vertex in graph.vertices.iter() {
+                for edge in vertex.edges.iter() {
+                    let count = (edge.count as f64 * scale).round() as usize;
+                    let next = graph.vertices[edge.vertex_index].value().clone();
+                    constructor.register_symbol_count(&vertex.context, next, count);
+                }
+            }
         }
+
+        Ok(constructor.construct())
     }
 
-    fn find_position(&self, value: char) -> Option<usize> {
-        for (index, vertex) in self.graph.vertices.iter().enumerate() {
-            if vertex.value == value {
-                return Some(index);
+    /// Follows the most likely edge from the start of a sequence until reaching an end state. `None` if the graph
+    /// wasn't built using [`GraphConstructor::register_whole_sequence`], or no end state is reached within
+    /// `max_length` values.
+    pub fn most_likely(&self, max_length: usize) -> Option<ScoredSequence<T>>
+    where
+        T: Eq + Hash + Clone,
+    {
+        let mut position = self.start_vertex()?;
+        let mut out = ScoredSequence {
+            values: Vec::new(),
+            log_probability: 0.0,
+        };
+
+        while !self.vertices[position].edges.is_empty() {
+            // Edges are sorted by ascending probability.
+            let edge = self.vertices[position].edges.last()?;
+            position = edge.vertex_index;
+            out.log_probability += (edge.probability as f64).ln();
+
+            if let Symbol::Value(value) = self.vertices[position].value() {
+                if out.values.len() == max_length {
+                    return None;
+                }
+                out.values.push(value.clone());
             }
         }
 
-        None
+        if self.reversed {
+            out.values.reverse();
+        }
+        Some(out)
     }
 
-    pub fn step(&mut self) -> Result<(), GraphStepperError> {
-        let random_value: f32 = fastrand::f32();
-        let mut selection: Option<usize> = None;
+    /// Searches for the `beam_width` most likely sequences of up to `max_length` values, going from the start of a
+    /// sequence to an end state, by only extending the `beam_width` most likely partial sequences at each step.
+    /// Sequences are returned from most to least likely. Being a heuristic, the search may miss sequences that
+    /// start out unlikely. Empty if `beam_width` is 0 or the graph wasn't built using
+    /// [`GraphConstructor::register_whole_sequence`].
+    pub fn beam_search(&self, beam_width: usize, max_length: usize) -> Vec<ScoredSequence<T>>
+    where
+        T: Eq + Hash + Clone,
+    {
+        let mut completed: Vec<ScoredSequence<T>> = Vec::new();
+        if beam_width == 0 {
+            return completed;
+        }
+        let Some(start) = self.start_vertex() else {
+            return completed;
+        };
 
-        let mut edges = &self.get_current_vertex().edges;
+        // Partial sequences, along with the index of the vertex they lead to.
+        let mut beam: Vec<(usize, ScoredSequence<T>)> = vec![(
+            start,
+            ScoredSequence {
+                values: Vec::new(),
+                log_probability: 0.0,
+            },
+        )];
+        while !beam.is_empty() {
+            let mut candidates: Vec<(usize, ScoredSequence<T>)> = Vec::new();
+
+            for (position, partial) in beam {
+                for edge in self.vertices[position].edges.iter() {
+                    let target = &self.vertices[edge.vertex_index];
+                    let mut values = partial.values.clone();
+                    if let Symbol::Value(value) = target.value() {
+                        if values.len() == max_length {
+                            continue;
+                        }
+                        values.push(value.clone());
+                    }
 
-        let mut override_edges: Vec<Edge>;
-        if let Some(min_length) = self.configuration.min_length {
-            if self.built_string.chars().count() < min_length {
-                override_edges = Vec::with_capacity(edges.capacity());
-
-                let mut lost_prob = 0.0;
-                for edge in edges.iter() {
-                    if self
-                        .graph
-                        .vertices
-                        .get(edge.vertex_index)
-                        .unwrap()
-                        .edges
-                        .is_empty()
-                    {
-                        lost_prob += edge.probability;
+                    let extended = ScoredSequence {
+                        values,
+                        log_probability: partial.log_probability + (edge.probability as f64).ln(),
+                    };
+                    if target.edges.is_empty() {
+                        completed.push(extended);
                     } else {
-                        override_edges.push(edge.clone());
+                        candidates.push((edge.vertex_index, extended));
                     }
                 }
-                lost_prob /= override_edges.len() as f32;
+            }
 
-                for edge in override_edges.iter_mut() {
-                    edge.probability += lost_prob; // Could floating point addition error bring the
-                                                   // edge array out of order?
-                }
+            completed.sort_by(|a, b| b.log_probability.total_cmp(&a.log_probability));
+            completed.truncate(beam_width);
+            candidates.sort_by(|a, b| b.1.log_probability.total_cmp(&a.1.log_probability));
+            candidates.truncate(beam_width);
 
-                edges = &override_edges;
+            // Extending a sequence only makes it less likely, so partial sequences that are already less likely than
+            // the last of the completed ones can't make the cut anymore.
+            if completed.len() == beam_width {
+                let threshold = completed[beam_width - 1].log_probability;
+                candidates.retain(|x| x.1.log_probability > threshold);
             }
+
+            beam = candidates;
         }
 
-        // The edges are ordered by probability, so we can just go from the top
-        // Here, the "Roulette Wheel Selection" algorithm is implemented.
-        let mut prob_sum = 0.0;
-        for edge in edges {
-            prob_sum += edge.probability;
-            if random_value < prob_sum {
-                selection = Some(edge.vertex_index);
-                break;
+        if self.reversed {
+            for sequence in completed.iter_mut() {
+                sequence.values.reverse();
             }
         }
+        completed
+    }
 
-        if selection.is_none() {
-            // Use last; this is an edge-case that might be reached by floating-pointer addition error.
-            // Probabilities might not add up to be high enough, but in any case this would then
-            // select the last, most probable value.
+    /// The natural logarithm of the probability of the graph generating `sequence` as a whole, from the start of a
+    /// sequence up to its end. See [`Graph::score`].
+    ///
+    /// # Panics
+    /// Panics if the probability of [`UnseenTransitions::Probability`] isn't in `(0, 1]`.
+    pub fn log_probability(&self, sequence: &[T], unseen: UnseenTransitions) -> f64
+    where
+        T: Eq + Hash + Clone,
+    {
+        self.score(sequence, unseen).log_probability
+    }
 
-            let last = self.get_current_vertex().edges.last();
-            match last {
-                Some(x) => selection = Some(x.vertex_index),
-                None => {
-                    // Reached dead end
-                    return Err(GraphStepperError::EdgeExhaustion);
+    /// Scores each transition of `sequence`, framed like by [`GraphConstructor::register_whole_sequence`], so that
+    /// the last transition is the one to [`Symbol::End`]. Transitions the graph doesn't contain are scored according
+    /// to `unseen`, after which scoring continues from the state they lead to, if it exists. With
+    /// [`GraphConstructor::smoothing`], every transition is scored by the smoothed probability instead, and `unseen`
+    /// only applies to those it still assigns a probability of 0. In reversed graphs, the steps are listed back to
+    /// front.
+    ///
+    /// # Panics
+    /// Panics if the probability of [`UnseenTransitions::Probability`] isn't in `(0, 1]`.
+    pub fn score(&self, sequence: &[T], unseen: UnseenTransitions) -> SequenceScore<T>
+    where
+        T: Eq + Hash + Clone,
+    {
+        unseen.assert_valid();
+
+        let mut symbols: Vec<Symbol<T>> = sequence.iter().cloned().map(Symbol::Value).collect();
+        if self.reversed {
+            symbols.reverse();
+        }
+        symbols.push(Symbol::End);
+
+        let mut out = SequenceScore {
+            log_probability: 0.0,
+            steps: Vec::with_capacity(symbols.len()),
+        };
+        let mut context = vec![Symbol::Start; self.order];
+        let mut position = self.find_vertex(&context);
+
+        for symbol in symbols {
+            let edge = position.and_then(|x| {
+                self.vertices[x]
+                    .edges
+                    .iter()
+                    .find(|edge| *self.vertices[edge.vertex_index].value() == symbol)
+            });
+            let probability = match (&self.smoothing_model, position) {
+                (Some(model), Some(x)) => {
+                    model.vertex_probability(self, self.smoothing, x, &symbol)
                 }
-            }
+                (Some(model), None) => model.context_probability(self.smoothing, &context, &symbol),
+                (None, _) => edge.map_or(0.0, |x| x.probability as f64),
+            };
+
+            context.remove(0);
+            context.push(symbol.clone());
+            position = match edge {
+                Some(edge) => Some(edge.vertex_index),
+                None => self.find_vertex(&context),
+            };
+
+            let seen = edge.is_some();
+            let log_probability = if probability > 0.0 {
+                probability.ln()
+            } else {
+                unseen.log_probability()
+            };
+
+            out.log_probability += log_probability;
+            out.steps.push(TransitionScore {
+                symbol,
+                log_probability,
+                seen,
+            });
         }
 
-        let new_position = selection.unwrap();
-        self.position = new_position;
-        self.built_string.push(self.get_current_vertex().value);
+        out
+    }
 
-        Ok(())
+    /// The index of the vertex at the start of a sequence, if there is one.
+    fn start_vertex(&self) -> Option<usize>
+    where
+        T: Eq + Hash + Clone,
+    {
+        self.find_vertex(&vec![Symbol::Start; self.order])
     }
 
-    pub fn step_until(
-        &mut self,
-        value: char,
-        timeout: usize,
-    ) -> Result<GraphStepperOut, GraphStepperError> {
-        if self.find_position(value).is_none() {
-            return Err(GraphStepperError::InvalidParameter(
-                InvalidConfigurationParameter::EndChar,
-            ));
-        }
+    /// The index of the vertex with the given context.
+    fn find_vertex(&self, context: &[Symbol<T>]) -> Option<usize>
+    where
+        T: Eq + Hash,
+    {
+        self.vertex_indices.get(context).copied()
+    }
 
-        loop {
-            if self.built_string.ends_with(value) {
-                return Ok(GraphStepperOut::Reached(self.flush()));
-            }
+    fn total_count(&self) -> usize {
+        self.vertices
+            .iter()
+            .flat_map(|x| x.edges.iter())
+            .map(|x| x.count)
+            .sum()
+    }
 
-            if self.built_string.len() >= timeout {
-                return Ok(GraphStepperOut::Timeout(self.flush()));
+    /// Derives edge probabilities from the transition counts, sorts the edges by them and precomputes the alias
+    /// tables used by [`GraphStepper::step`]. Must be called whenever counts change.
+    fn update_probabilities(&mut self) {
+        for vertex in self.vertices.iter_mut() {
+            let count_sum: usize = vertex.edges.iter().map(|x| x.count).sum();
+            for edge in vertex.edges.iter_mut() {
+                edge.probability = edge.count as f32 / count_sum as f32;
             }
 
-            if let Err(_exhaustion) = self.step() {
-                return Ok(GraphStepperOut::Exhausted(self.flush()));
-            }
+            // Ties are broken by vertex index so that the edge order, and thereby seeded sampling, doesn't depend
+            // on the iteration order of the constructor's `HashMap`s.
+            vertex.edges.sort_by(|a, b| {
+                a.probability
+                    .partial_cmp(&b.probability)
+                    .unwrap()
+                    .then(a.vertex_index.cmp(&b.vertex_index))
+            });
         }
+
+        for vertex_index in 0..self.vertices.len() {
+            let vertex = &self.vertices[vertex_index];
+            let counts: Vec<f32> = vertex.edges.iter().map(|x| x.count as f32).collect();
+
+            let is_dead_end: Vec<bool> = vertex
+                .edges
+                .iter()
+                .map(|x| self.vertices[x.vertex_index].edges.is_empty())
+                .collect();
+            let dead_end_count = is_dead_end.iter().filter(|x| **x).count();
+
+            let min_length_sampler = if dead_end_count == 0 || dead_end_count == is_dead_end.len() {
+                None
+            } else {
+                let weights: Vec<f32> = counts
+                    .iter()
+                    .zip(is_dead_end.iter())
+                    .map(|(count, dead_end)| if *dead_end { 0.0 } else { *count })
+                    .collect();
+
+                Some(AliasTable::new(&weights))
+            };
+
+            let vertex = &mut self.vertices[vertex_index];
+            vertex.sampler = AliasTable::new(&counts);
+            vertex.min_length_sampler = min_length_sampler;
+        }
+
+        self.update_end_distances();
     }
 
-    pub fn step_until_end_state(
-        &mut self,
-        timeout: usize,
-    ) -> Result<GraphStepperOut, GraphStepperError> {
-        loop {
-            if self.built_string.len() >= timeout {
-                return Ok(GraphStepperOut::Timeout(self.flush()));
+    /// Precomputes the statistics [`Graph::score`] needs for the graph's smoothing. Must be called whenever counts
+    /// change.
+    fn update_smoothing_model(&mut self)
+    where
+        T: Eq + Hash + Clone,
+    {
+        self.smoothing_model = match self.smoothing {
+            Smoothing::None => None,
+            smoothing => Some(SmoothingModel::new(self, smoothing)),
+        };
+    }
+
+    /// Computes the shortest distance of every vertex to a dead end, counted in emitted values, using a 0-1 BFS on
+    /// the reversed edges.
+    fn update_end_distances(&mut self) {
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); self.vertices.len()];
+        for (index, vertex) in self.vertices.iter().enumerate() {
+            for edge in vertex.edges.iter() {
+                predecessors[edge.vertex_index].push(index);
+            }
+        }
+
+        let mut distances = vec![usize::MAX; self.vertices.len()];
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        for (index, vertex) in self.vertices.iter().enumerate() {
+            if vertex.edges.is_empty() {
+                distances[index] = 0;
+                queue.push_back(index);
             }
+        }
 
-            if let Err(_exhaustion) = self.step() {
-                return Ok(GraphStepperOut::Reached(self.flush()));
+        while let Some(index) = queue.pop_front() {
+            let emitted = self.vertices[index].emits_value() as usize;
+            let distance = distances[index] + emitted;
+            for predecessor in predecessors[index].iter() {
+                if distance < distances[*predecessor] {
+                    distances[*predecessor] = distance;
+                    if emitted == 0 {
+                        queue.push_front(*predecessor);
+                    } else {
+                        queue.push_back(*predecessor);
+                    }
+                }
             }
         }
+
+        for (vertex, distance) in self.vertices.iter_mut().zip(distances) {
+            vertex.end_distance = distance;
+        }
+
+        for index in 0..self.vertices.len() {
+            let max_edge_end_distance = self.vertices[index]
+                .edges
+                .iter()
+                .map(|x| self.edge_end_distance(x))
+                .max()
+                .unwrap_or(0);
+
+            self.vertices[index].max_edge_end_distance = max_edge_end_distance;
+        }
+    }
+
+    /// Walks the edges emitting `values` in order, starting at the vertex with index `start`. Returns the index of
+    /// the vertex reached, or `None` if the values can't be generated from there.
+    fn follow<'a>(&self, start: usize, values: impl IntoIterator<Item = &'a T>) -> Option<usize>
+    where
+        T: PartialEq + 'a,
+    {
+        let mut position = start;
+        for value in values {
+            position = self.vertices[position]
+                .edges
+                .iter()
+                .map(|x| x.vertex_index)
+                .find(|x| matches!(self.vertices[*x].value(), Symbol::Value(target) if target == value))?;
+        }
+
+        Some(position)
     }
 
-    pub fn flush(&mut self) -> String {
-        let out = self.built_string.clone();
-        self.built_string = String::new();
+    /// For every `k` up to `max_length`, computes the probability of each vertex reaching a dead end after emitting
+    /// exactly `k` more values, going backwards from the dead ends.
+    fn exact_length_probabilities(&self, max_length: usize) -> Vec<Vec<f64>> {
+        let mut probabilities: Vec<Vec<f64>> = Vec::with_capacity(max_length + 1);
+
+        for k in 0..=max_length {
+            let row: Vec<f64> = self
+                .vertices
+                .iter()
+                .map(|vertex| {
+                    if vertex.edges.is_empty() {
+                        return if k == 0 { 1.0 } else { 0.0 };
+                    }
 
-        self.reset_position();
+                    vertex
+                        .edges
+                        .iter()
+                        .map(|edge| {
+                            let target = &self.vertices[edge.vertex_index];
+                            let target_k = match k.checked_sub(target.emits_value() as usize) {
+                                Some(x) => x,
+                                None => return 0.0,
+                            };
+
+                            let target_probability = if target.edges.is_empty() {
+                                (target_k == 0) as u8 as f64
+                            } else if target_k < k {
+                                probabilities[target_k][edge.vertex_index]
+                            } else {
+                                // Only dead ends, i.e. sequence ends, are entered without emitting a value.
+                                0.0
+                            };
+
+                            edge.probability as f64 * target_probability
+                        })
+                        .sum()
+                })
+                .collect();
+
+            probabilities.push(row);
+        }
 
-        out
+        probabilities
     }
 
-    fn random_position(&self) -> usize {
-        fastrand::usize(0..self.graph.vertices.len())
+    /// Counts the distinct sequences completed by walking from the vertex with index `start` to a dead end, which
+    /// is the number of such paths. `None` if there are infinitely many, i.e. a cycle can be entered that doesn't
+    /// rule out reaching a dead end. Saturates at `u128::MAX`.
+    fn count_sequences(&self, start: usize) -> Option<u128> {
+        if self.vertices[start].end_distance == usize::MAX {
+            return Some(0);
+        }
+
+        // Depth first search, where each stack entry holds a vertex index and the index of its next edge.
+        let mut counts: Vec<Option<u128>> = vec![None; self.vertices.len()];
+        let mut on_stack = vec![false; self.vertices.len()];
+        let mut stack: Vec<(usize, usize)> = vec![(start, 0)];
+        on_stack[start] = true;
+
+        while let Some((index, edge_index)) = stack.last().copied() {
+            let vertex = &self.vertices[index];
+            match vertex.edges.get(edge_index) {
+                Some(edge) => {
+                    stack.last_mut().unwrap().1 += 1;
+
+                    // Vertices that can't reach a dead end don't complete any sequences.
+                    let target = edge.vertex_index;
+                    if counts[target].is_some() || self.vertices[target].end_distance == usize::MAX
+                    {
+                        continue;
+                    }
+                    if on_stack[target] {
+                        return None;
+                    }
+
+                    on_stack[target] = true;
+                    stack.push((target, 0));
+                }
+                None => {
+                    stack.pop();
+                    on_stack[index] = false;
+
+                    counts[index] = Some(if vertex.edges.is_empty() {
+                        1
+                    } else {
+                        vertex
+                            .edges
+                            .iter()
+                            .map(|x| counts[x.vertex_index].unwrap_or(0))
+                            .fold(0, u128::saturating_add)
+                    });
+                }
+            }
+        }
+
+        counts[start]
     }
 
-    fn get_current_vertex(&self) -> &Vertex {
-        self.graph.vertices.get(self.position).unwrap()
+    /// The least number of values emitted until reaching a dead end when taking `edge`.
+    fn edge_end_distance(&self, edge: &Edge) -> usize {
+        let target = &self.vertices[edge.vertex_index];
+        target
+            .end_distance
+            .saturating_add(target.emits_value() as usize)
     }
 }
 
-pub enum GraphStepperOut {
-    Reached(String),
-    Timeout(String),
-    /// Not conforming to configuration, but out of edges pointing to anything other than end states.
-    Exhausted(String),
+/// A sequence found by [`Graph::most_likely`] or [`Graph::beam_search`].
+#[derive(Clone, Debug)]
+pub struct ScoredSequence<T> {
+    pub values: Vec<T>,
+    /// Natural logarithm of the probability of the graph generating this sequence.
+    pub log_probability: f64,
 }
 
-impl Display for GraphStepperOut {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let out = match self {
-            GraphStepperOut::Timeout(x) => x,
-            GraphStepperOut::Reached(x) => x,
-            GraphStepperOut::Exhausted(x) => x,
-        };
+/// How [`Graph::score`] treats transitions that the graph doesn't contain.
+#[derive(Clone, Copy, Debug)]
+pub enum UnseenTransitions {
+    /// Unseen transitions have a probability of 0, so sequences containing them have a log-probability of negative
+    /// infinity.
+    Impossible,
+    /// Unseen transitions are assigned this probability instead. Must be in `(0, 1]`.
+    Probability(f64),
+}
 
-        f.write_fmt(format_args!("{}", out))
+impl UnseenTransitions {
+    fn assert_valid(&self) {
+        if let UnseenTransitions::Probability(x) = self {
+            assert!(
+                *x > 0.0 && *x <= 1.0,
+                "Unseen transition probability must be in (0, 1]"
+            );
+        }
+    }
+
+    fn log_probability(&self) -> f64 {
+        match self {
+            UnseenTransitions::Impossible => f64::NEG_INFINITY,
+            UnseenTransitions::Probability(x) => x.ln(),
+        }
+    }
+}
+
+/// The result of [`Graph::score`].
+#[derive(Clone, Debug)]
+pub struct SequenceScore<T> {
+    /// Natural logarithm of the probability of the whole sequence, i.e. the sum over all steps.
+    pub log_probability: f64,
+    pub steps: Vec<TransitionScore<T>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransitionScore<T> {
+    /// The value transitioned to, or [`Symbol::End`] for the final transition.
+    pub symbol: Symbol<T>,
+    pub log_probability: f64,
+    /// Whether the graph contains the transition.
+    pub seen: bool,
+}
+
+#[derive(Debug)]
+pub enum GraphMergeError {
+    NoGraphs,
+    /// Only graphs of the same order can be merged.
+    OrderMismatch,
+    /// Reversed graphs can only be merged with other reversed graphs.
+    DirectionMismatch,
+    /// Weights must be finite, non-negative and not all 0.
+    InvalidWeights,
+}
+
+impl Display for GraphMergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let error_message = match self {
+            GraphMergeError::NoGraphs => "No graphs provided to merge",
+            GraphMergeError::OrderMismatch => "Graphs of different orders can't be merged",
+            GraphMergeError::DirectionMismatch => "Reversed and regular graphs can't be merged",
+            GraphMergeError::InvalidWeights => "Weights must be finite, non-negative and not all 0",
+        };
+
+        f.write_str(error_message)
+    }
+}
+
+impl Error for GraphMergeError {}
+
+/// The most distinct [`GraphStepperConfiguration::required_sequences`] a stepper supports, since precomputing them
+/// takes time and memory exponential in their number. Large graphs support fewer, see
+/// [`InvalidConfigurationParameter::Constraints`].
+pub const MAX_REQUIRED_SEQUENCES: usize = 8;
+
+pub struct GraphStepper<T> {
+    graph: Arc<Graph<T>>,
+    rng: fastrand::Rng,
+    /// Scratch buffer for edge weights that have to be computed while stepping.
+    weights: Vec<f64>,
+    /// For `exact_length`, the probability of each vertex reaching a dead end after emitting exactly `k` more
+    /// values, indexed by `k` and then by vertex index.
+    exact_length_probabilities: Option<Arc<Vec<Vec<f64>>>>,
+    /// For `banned_values` and `required_sequences`, which states can still satisfy them.
+    constraints: Option<Arc<ConstraintTable<T>>>,
+    /// Progress of the built sequence towards the required sequences.
+    constraint_state: ConstraintState,
+    /// For `backoff_threshold`, the transitions of every shorter context.
+    backoff: Option<Arc<BackoffTable>>,
+    /// For `min_edit_distance`, the graph's training sequences indexed for fast lookup.
+    sequence_index: Option<Arc<SequenceIndex>>,
+    /// Where to return to after flushing. `None` if a random vertex should be picked instead.
+    start_position: Option<usize>,
+    position: usize,
+    built_sequence: Vec<T>,
+    configuration: GraphStepperConfiguration<T>,
+}
+
+/// Clones share the graph and precomputed tables, but get a freshly seeded random number generator, so that steppers
+/// cloned for several threads don't all generate the same sequences. To make each clone's output reproducible, create
+/// it with [`GraphStepper::with_seed`] instead.
+impl<T: Clone> Clone for GraphStepper<T> {
+    fn clone(&self) -> Self {
+        Self {
+            graph: self.graph.clone(),
+            rng: fastrand::Rng::new(),
+            weights: Vec::new(),
+            exact_length_probabilities: self.exact_length_probabilities.clone(),
+            constraints: self.constraints.clone(),
+            constraint_state: self.constraint_state,
+            backoff: self.backoff.clone(),
+            sequence_index: self.sequence_index.clone(),
+            start_position: self.start_position,
+            position: self.position,
+            built_sequence: self.built_sequence.clone(),
+            configuration: self.configuration.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[allow(non_snake_case)]
+pub struct GraphStepperConfiguration<T> {
+    /// Start from the state reached once a sequence begins with this value, i.e. the one whose context is
+    /// [`Symbol::Start`] repeated `order - 1` times, followed by the value. The value itself isn't part of the
+    /// generated sequences. In reversed graphs, it's the last value of a sequence instead. Chains of an order above
+    /// 1 only contain these states if built using [`GraphConstructor::register_whole_sequence`], see `prefix` for
+    /// starting with several values. If `None`, steppers start at the beginning of a sequence if the graph was built
+    /// using [`GraphConstructor::register_whole_sequence`], or at a random vertex otherwise.
+    pub start_value: Option<T>,
+    pub min_length: Option<usize>,
+    /// Once a sequence approaches this length, only edges that still allow reaching an end state in time are
+    /// taken. If there are none, the stepper heads for the nearest end state instead.
+    pub max_length: Option<usize>,
+    /// Only generate sequences of exactly this many values. Sampling stays proportional to the chain's
+    /// probabilities, conditioned on the length. Takes precedence over `min_length` and `max_length`.
+    pub exact_length: Option<usize>,
+    /// Every sequence starts with these values, and generation continues from the state they lead to. Requires a
+    /// fixed start position, i.e. `start_value` or a graph built with [`GraphConstructor::register_whole_sequence`].
+    /// Not supported by reversed graphs.
+    pub prefix: Vec<T>,
+    /// Every sequence ends with these values. Requires a reversed graph built with
+    /// [`GraphConstructor::register_whole_sequence`], which generates the rest of the sequence backwards from them.
+    pub suffix: Vec<T>,
+    /// Never generate any of these values. Edges leading to them are removed, and the remaining ones are sampled
+    /// according to the chain's probabilities, conditioned on completing a sequence without banned values.
+    pub banned_values: Vec<T>,
+    /// Every sequence contains each of these runs of consecutive values. Like with `banned_values`, only edges that
+    /// still allow completing such a sequence are taken, weighted by how likely they are to do so. Precomputation
+    /// takes time and memory proportional to the graph size, doubling with each required sequence, so at most
+    /// [`MAX_REQUIRED_SEQUENCES`] distinct ones are supported, and fewer on large graphs. Duplicates are ignored.
+    ///
+    /// Combined with `min_length`, `max_length` or `exact_length`, length steering doesn't take these constraints
+    /// into account, so sampling may run out of edges even though a sequence satisfying all of them exists.
+    pub required_sequences: Vec<Vec<T>>,
+    /// Sequences that are fewer than this many insertions, deletions or substitutions away from any training
+    /// sequence are returned as [`GraphStepperOut::Rejected`] by [`GraphStepper::step_until_end_state`]. `Some(1)`
+    /// rejects verbatim reproductions. Requires a graph built with [`GraphConstructor::keep_sequences`].
+    pub min_edit_distance: Option<usize>,
+    /// Sharpens (below 1) or flattens (above 1) the transition probabilities by raising them to the power of
+    /// `1 / temperature`. Must be positive.
+    pub temperature: Option<f64>,
+    /// Only sample from the `top_k` most likely edges that conform to the rest of the configuration. Must be
+    /// positive.
+    pub top_k: Option<usize>,
+    /// Only sample from the most likely edges that conform to the rest of the configuration, up to a cumulative
+    /// probability of `top_p`. Must be in `(0, 1]`.
+    pub top_p: Option<f64>,
+    /// States observed fewer than this many times sample their next value from the longest shorter context, i.e.
+    /// their most recent values, that was observed at least this often, falling back to the overall frequencies
+    /// of the values, like in PPM. This keeps rarely observed states of high-order chains from reproducing the
+    /// dataset verbatim, and states without any transitions from exhausting the stepper, so that only the end of a
+    /// sequence stops it. `Some(1)` only backs off from the latter. Must be positive.
+    ///
+    /// Steps that back off disregard `max_length`, `temperature`, `top_k` and `top_p`. Can't be combined with
+    /// `exact_length`, `banned_values` or `required_sequences`.
+    pub backoff_threshold: Option<usize>,
+}
+
+impl<T> Default for GraphStepperConfiguration<T> {
+    fn default() -> Self {
+        Self {
+            start_value: None,
+            min_length: None,
+            max_length: None,
+            exact_length: None,
+            prefix: Vec::new(),
+            suffix: Vec::new(),
+            banned_values: Vec::new(),
+            required_sequences: Vec::new(),
+            min_edit_distance: None,
+            temperature: None,
+            top_k: None,
+            top_p: None,
+            backoff_threshold: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GraphStepperError {
+    InvalidParameter(InvalidConfigurationParameter),
+    EdgeExhaustion,
+}
+
+impl Display for GraphStepperError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let error_message: String = match self {
+            GraphStepperError::EdgeExhaustion => {
+                "No edges left to create configuration-compliant sample".to_string()
+            }
+            GraphStepperError::InvalidParameter(param) => {
+                let param_name = match param {
+                    InvalidConfigurationParameter::EndValue => "End Value",
+                    InvalidConfigurationParameter::StartValue => "Start Value",
+                    InvalidConfigurationParameter::Prefix => "Prefix",
+                    InvalidConfigurationParameter::Suffix => "Suffix",
+                    InvalidConfigurationParameter::ExactLength => "Exact Length",
+                    InvalidConfigurationParameter::Constraints => {
+                        "Banned Values or Required Sequences"
+                    }
+                    InvalidConfigurationParameter::MinEditDistance => "Min Edit Distance",
+                    InvalidConfigurationParameter::Temperature => "Temperature",
+                    InvalidConfigurationParameter::TopK => "Top K",
+                    InvalidConfigurationParameter::TopP => "Top P",
+                    InvalidConfigurationParameter::BackoffThreshold => "Backoff Threshold",
+                };
+                format!("Invalid parameter provided: {param_name}")
+            }
+        };
+
+        f.write_str(&error_message)
+    }
+}
+
+impl Error for GraphStepperError {}
+
+#[derive(Debug)]
+pub enum InvalidConfigurationParameter {
+    /// No sequence begins with the start value.
+    StartValue,
+    EndValue,
+    /// The prefix can't be generated from the start position, or there is no fixed start position.
+    Prefix,
+    /// The suffix can't end a sequence, or the graph isn't reversed.
+    Suffix,
+    /// No sequence of exactly this length can be generated from the start position.
+    ExactLength,
+    /// No sequence without banned values and containing all required sequences can be generated from the start
+    /// position, or there are more than [`MAX_REQUIRED_SEQUENCES`] distinct required sequences. Also returned if the
+    /// required sequences are too many or too long for the graph size, i.e. precomputing them would take more than
+    /// about 16 million states of a vertex, the progress within the required sequences and which ones were found.
+    Constraints,
+    /// The graph doesn't keep its training sequences.
+    MinEditDistance,
+    Temperature,
+    TopK,
+    TopP,
+    /// The threshold is 0, or combined with `exact_length`, `banned_values` or `required_sequences`.
+    BackoffThreshold,
+}
+
+impl<T: Eq + Hash + Clone> GraphStepper<T> {
+    pub fn new(
+        graph: Arc<Graph<T>>,
+        configuration: GraphStepperConfiguration<T>,
+    ) -> Result<Self, GraphStepperError> {
+        Self::with_rng(graph, configuration, fastrand::Rng::new())
+    }
+
+    /// Creates a stepper whose random number generator is seeded with `seed`, making its output reproducible.
+    pub fn with_seed(
+        graph: Arc<Graph<T>>,
+        configuration: GraphStepperConfiguration<T>,
+        seed: u64,
+    ) -> Result<Self, GraphStepperError> {
+        Self::with_rng(graph, configuration, fastrand::Rng::with_seed(seed))
+    }
+
+    fn with_rng(
+        graph: Arc<Graph<T>>,
+        configuration: GraphStepperConfiguration<T>,
+        rng: fastrand::Rng,
+    ) -> Result<Self, GraphStepperError> {
+        let mut out = Self {
+            graph,
+            rng,
+            weights: Vec::new(),
+            exact_length_probabilities: None,
+            constraints: None,
+            constraint_state: ConstraintState::default(),
+            backoff: None,
+            sequence_index: None,
+            start_position: None,
+            position: 0,
+            built_sequence: Vec::new(),
+            configuration,
+        };
+
+        out.start_position = match &out.configuration.start_value {
+            Some(start_value) => {
+                let mut context = vec![Symbol::Start; out.graph.order - 1];
+                context.push(Symbol::Value(start_value.clone()));
+                match out.graph.find_vertex(&context) {
+                    Some(x) => Some(x),
+                    None => {
+                        return Err(GraphStepperError::InvalidParameter(
+                            InvalidConfigurationParameter::StartValue,
+                        ))
+                    }
+                }
+            }
+            None => out.graph.start_vertex(),
+        };
+
+        if !out.configuration.prefix.is_empty() {
+            let prefix_position = out
+                .start_position
+                .filter(|_| !out.graph.reversed)
+                .and_then(|x| out.graph.follow(x, &out.configuration.prefix));
+            match prefix_position {
+                Some(x) => out.start_position = Some(x),
+                None => {
+                    return Err(GraphStepperError::InvalidParameter(
+                        InvalidConfigurationParameter::Prefix,
+                    ))
+                }
+            }
+        }
+
+        if !out.configuration.suffix.is_empty() {
+            // Reversed graphs start at the end of a sequence, so the suffix is walked from its last value.
+            let suffix_position = out
+                .graph
+                .start_vertex()
+                .filter(|_| out.graph.reversed)
+                .and_then(|x| out.graph.follow(x, out.configuration.suffix.iter().rev()));
+            match suffix_position {
+                Some(x) => out.start_position = Some(x),
+                None => {
+                    return Err(GraphStepperError::InvalidParameter(
+                        InvalidConfigurationParameter::Suffix,
+                    ))
+                }
+            }
+        }
+
+        if let Some(min_edit_distance) = out.configuration.min_edit_distance {
+            let Some(sequences) = &out.graph.sequences else {
+                return Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::MinEditDistance,
+                ));
+            };
+
+            if min_edit_distance > 0 {
+                out.sequence_index = Some(Arc::new(SequenceIndex::new(sequences)));
+            }
+        }
+
+        if out
+            .configuration
+            .temperature
+            .is_some_and(|x| !x.is_finite() || x <= 0.0)
+        {
+            return Err(GraphStepperError::InvalidParameter(
+                InvalidConfigurationParameter::Temperature,
+            ));
+        }
+        if out.configuration.top_k == Some(0) {
+            return Err(GraphStepperError::InvalidParameter(
+                InvalidConfigurationParameter::TopK,
+            ));
+        }
+        if out
+            .configuration
+            .top_p
+            .is_some_and(|x| !(x > 0.0 && x <= 1.0))
+        {
+            return Err(GraphStepperError::InvalidParameter(
+                InvalidConfigurationParameter::TopP,
+            ));
+        }
+
+        if let Some(backoff_threshold) = out.configuration.backoff_threshold {
+            if backoff_threshold == 0
+                || out.configuration.exact_length.is_some()
+                || !out.configuration.banned_values.is_empty()
+                || !out.configuration.required_sequences.is_empty()
+            {
+                return Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::BackoffThreshold,
+                ));
+            }
+
+            out.backoff = Some(Arc::new(BackoffTable::new(&out.graph)));
+        }
+
+        if let Some(exact_length) = out.configuration.exact_length {
+            out.exact_length_probabilities =
+                Some(Arc::new(out.graph.exact_length_probabilities(exact_length)));
+        }
+
+        if !out.configuration.banned_values.is_empty()
+            || !out.configuration.required_sequences.is_empty()
+        {
+            let mut distinct: HashSet<Vec<T>> = HashSet::new();
+            out.configuration
+                .required_sequences
+                .retain(|x| distinct.insert(x.clone()));
+            if out.configuration.required_sequences.len() > MAX_REQUIRED_SEQUENCES {
+                return Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::Constraints,
+                ));
+            }
+
+            let Some(constraints) = ConstraintTable::new(
+                &out.graph,
+                &out.configuration.banned_values,
+                &out.configuration.required_sequences,
+            ) else {
+                return Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::Constraints,
+                ));
+            };
+            out.constraints = Some(Arc::new(constraints));
+        }
+
+        out.reset_position();
+
+        if let Some(probabilities) = &out.exact_length_probabilities {
+            // Prefixes and suffixes count towards the length.
+            let remaining = out
+                .configuration
+                .exact_length
+                .and_then(|x| x.checked_sub(out.built_sequence.len()));
+            let is_possible =
+                |vertex: usize| remaining.is_some_and(|k| probabilities[k][vertex] > 0.0);
+            let possible = match out.start_position {
+                Some(_) => is_possible(out.position),
+                None => (0..out.graph.vertices.len()).any(is_possible),
+            };
+            if !possible {
+                return Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::ExactLength,
+                ));
+            }
+        }
+
+        if let (Some(constraints), Some(_)) = (&out.constraints, out.start_position) {
+            if out.built_sequence.iter().any(|x| constraints.is_banned(x))
+                || !constraints.is_viable(out.position, out.constraint_state)
+            {
+                return Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::Constraints,
+                ));
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn reset_position(&mut self) {
+        match self.start_position {
+            Some(x) => self.position = x,
+            None => self.position = self.random_position(),
+        }
+
+        // The start position already points past the prefix, or the suffix in reversed graphs, which build
+        // sequences back to front.
+        if self.graph.reversed {
+            self.built_sequence
+                .extend(self.configuration.suffix.iter().rev().cloned());
+        } else {
+            self.built_sequence
+                .extend(self.configuration.prefix.iter().cloned());
+        }
+
+        if let Some(constraints) = &self.constraints {
+            self.constraint_state = self
+                .built_sequence
+                .iter()
+                .fold(constraints.initial_state(), |state, value| {
+                    constraints.advance(state, value)
+                });
+        }
+    }
+
+    /// The number of distinct sequences that can be generated from the start position, disregarding every
+    /// configuration option other than `start_value`, `prefix` and `suffix`, so it's an upper bound if others are
+    /// set. `None` if there are infinitely many, the stepper starts at random vertices or `backoff_threshold` is set,
+    /// since backing off can take transitions the graph doesn't contain.
+    pub fn sequence_count(&self) -> Option<u128> {
+        if self.backoff.is_some() {
+            return None;
+        }
+
+        self.graph.count_sequences(self.start_position?)
+    }
+
+    /// The rolling window of the last `order` symbols that make up the current state.
+    pub fn context(&self) -> &[Symbol<T>] {
+        &self.get_current_vertex().context
+    }
+
+    pub fn step(&mut self) -> Result<(), GraphStepperError> {
+        self.position = match self.sample_target() {
+            Some(x) => x,
+            None => {
+                // Reached dead end, or no edge conforms to the configuration
+                return Err(GraphStepperError::EdgeExhaustion);
+            }
+        };
+        if let Symbol::Value(value) = self.graph.vertices[self.position].value() {
+            if let Some(constraints) = &self.constraints {
+                self.constraint_state = constraints.advance(self.constraint_state, value);
+            }
+            self.built_sequence.push(value.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Steps until `value` is emitted or `timeout` values have been generated.
+    pub fn step_until(
+        &mut self,
+        value: T,
+        timeout: usize,
+    ) -> Result<GraphStepperOut<T>, GraphStepperError> {
+        if !self
+            .graph
+            .vertices
+            .iter()
+            .any(|x| matches!(x.value(), Symbol::Value(x) if *x == value))
+        {
+            return Err(GraphStepperError::InvalidParameter(
+                InvalidConfigurationParameter::EndValue,
+            ));
+        }
+
+        loop {
+            if self.built_sequence.last() == Some(&value) {
+                return Ok(GraphStepperOut::Reached(self.flush()));
+            }
+
+            if self.built_sequence.len() >= timeout {
+                return Ok(GraphStepperOut::Timeout(self.flush()));
+            }
+
+            if let Err(_exhaustion) = self.step() {
+                return Ok(GraphStepperOut::Exhausted(self.flush()));
+            }
+        }
+    }
+
+    /// Steps until a vertex without outgoing edges is reached or `timeout` values have been generated. Sequences
+    /// too close to a training sequence, as configured by `min_edit_distance`, are returned as rejected.
+    pub fn step_until_end_state(
+        &mut self,
+        timeout: usize,
+    ) -> Result<GraphStepperOut<T>, GraphStepperError> {
+        loop {
+            if self.built_sequence.len() >= timeout {
+                return Ok(GraphStepperOut::Timeout(self.flush()));
+            }
+
+            if let Err(_exhaustion) = self.step() {
+                if self.get_current_vertex().edges.is_empty() {
+                    let out = self.flush();
+                    if self.resembles_training_sequence(&out) {
+                        return Ok(GraphStepperOut::Rejected(out));
+                    }
+
+                    return Ok(GraphStepperOut::Reached(out));
+                }
+
+                // Edges are left, but none of them conform to the configuration.
+                return Ok(GraphStepperOut::Exhausted(self.flush()));
+            }
+        }
+    }
+
+    /// Returns the built sequence in reading order and starts over. Sequences generated by reversed graphs are
+    /// turned around.
+    pub fn flush(&mut self) -> Vec<T> {
+        let mut out = std::mem::take(&mut self.built_sequence);
+        if self.graph.reversed {
+            out.reverse();
+        }
+
+        self.reset_position();
+
+        out
+    }
+
+    /// Like [`GraphStepper::flush`], but appends the built sequence to `sink` instead of allocating a new `Vec`.
+    pub fn flush_into<E: Extend<T>>(&mut self, sink: &mut E) {
+        if self.graph.reversed {
+            sink.extend(self.built_sequence.drain(..).rev());
+        } else {
+            sink.extend(self.built_sequence.drain(..));
+        }
+
+        self.reset_position();
+    }
+
+    /// Picks the index of the vertex to step onto next, backing off to a shorter context if the current vertex was
+    /// observed fewer than `backoff_threshold` times.
+    fn sample_target(&mut self) -> Option<usize> {
+        if let (Some(backoff), Some(threshold)) =
+            (&self.backoff, self.configuration.backoff_threshold)
+        {
+            let vertex = &self.graph.vertices[self.position];
+            let count: usize = vertex.edges.iter().map(|x| x.count).sum();
+            // The end of a sequence stops the stepper as usual.
+            if count < threshold && !matches!(vertex.value(), Symbol::End) {
+                let length = backoff.context_length(self.position, threshold);
+                let avoid_dead_ends = self
+                    .configuration
+                    .min_length
+                    .is_some_and(|x| self.built_sequence.len() < x);
+                return backoff.sample(
+                    &self.graph,
+                    self.position,
+                    length,
+                    avoid_dead_ends,
+                    &mut self.rng,
+                );
+            }
+        }
+
+        let edge_index = self.sample_edge()?;
+        Some(self.get_current_vertex().edges[edge_index].vertex_index)
+    }
+
+    /// Picks the index of the edge of the current vertex to take next, honoring the configuration.
+    fn sample_edge(&mut self) -> Option<usize> {
+        if self.exact_length_probabilities.is_some() {
+            return self.sample_exact_length();
+        }
+
+        // Borrowing the graph field directly keeps `self.rng` available for sampling.
+        let vertex = &self.graph.vertices[self.position];
+        let length = self.built_sequence.len();
+
+        if let Some(max_length) = self.configuration.max_length {
+            if length.saturating_add(vertex.max_edge_end_distance) > max_length {
+                return self.sample_towards_end(max_length);
+            }
+        }
+
+        if self.constraints.is_some() {
+            return self.sample_constrained();
+        }
+
+        if self.configuration.temperature.is_some()
+            || self.configuration.top_k.is_some()
+            || self.configuration.top_p.is_some()
+        {
+            return self.sample_shaped();
+        }
+
+        let mut sampler = &vertex.sampler;
+        if let Some(min_length) = self.configuration.min_length {
+            if length < min_length {
+                if let Some(min_length_sampler) = &vertex.min_length_sampler {
+                    sampler = min_length_sampler;
+                }
+            }
+        }
+
+        sampler.sample(&mut self.rng)
+    }
+
+    /// Samples an edge of the current vertex among those that still allow reaching an end state within
+    /// `max_length` values, with their probabilities scaled up proportionally. If there are none, only the edges
+    /// leading to the nearest end states are considered.
+    fn sample_towards_end(&mut self, max_length: usize) -> Option<usize> {
+        let vertex = &self.graph.vertices[self.position];
+        let length = self.built_sequence.len();
+
+        // Edges ruled out by banned values or required sequences don't count.
+        let distances: Vec<Option<usize>> = vertex
+            .edges
+            .iter()
+            .map(|x| {
+                self.edge_allowed(x)
+                    .then(|| self.graph.edge_end_distance(x))
+            })
+            .collect();
+        let max_distance = match max_length.checked_sub(length) {
+            Some(remaining) if distances.iter().flatten().any(|x| *x <= remaining) => remaining,
+            _ => *distances.iter().flatten().min()?,
+        };
+
+        let mut weights = std::mem::take(&mut self.weights);
+        weights.clear();
+        weights.extend(
+            vertex
+                .edges
+                .iter()
+                .zip(distances.iter())
+                .map(|(edge, distance)| match distance {
+                    Some(distance) if *distance <= max_distance => edge.count as f64,
+                    _ => 0.0,
+                }),
+        );
+        self.avoid_dead_ends(&mut weights);
+        self.shape_weights(&mut weights);
+
+        let selection = sample_weighted(&mut self.rng, &weights);
+        self.weights = weights;
+
+        selection
+    }
+
+    /// Samples an edge of the current vertex proportionally to its probability times the probability of its target
+    /// reaching a dead end after exactly the number of values that are still missing.
+    fn sample_exact_length(&mut self) -> Option<usize> {
+        let probabilities = self.exact_length_probabilities.as_ref()?;
+        let vertex = &self.graph.vertices[self.position];
+        let remaining = self
+            .configuration
+            .exact_length?
+            .checked_sub(self.built_sequence.len())?;
+
+        let mut weights = std::mem::take(&mut self.weights);
+        weights.clear();
+        weights.extend(vertex.edges.iter().map(|edge| {
+            let target = &self.graph.vertices[edge.vertex_index];
+            match remaining.checked_sub(target.emits_value() as usize) {
+                Some(k) if self.edge_allowed(edge) => {
+                    edge.probability as f64 * probabilities[k][edge.vertex_index]
+                }
+                _ => 0.0,
+            }
+        }));
+        self.shape_weights(&mut weights);
+
+        let selection = sample_weighted(&mut self.rng, &weights);
+        self.weights = weights;
+
+        selection
+    }
+
+    /// Samples an edge of the current vertex proportionally to its probability times the probability of its target
+    /// completing a sequence that satisfies `banned_values` and `required_sequences`. Should those be too small to
+    /// represent, the edges that can still satisfy the constraints are sampled by their probabilities alone.
+    fn sample_constrained(&mut self) -> Option<usize> {
+        let constraints = self.constraints.as_ref()?;
+        let vertex = &self.graph.vertices[self.position];
+
+        let mut weights = std::mem::take(&mut self.weights);
+        weights.clear();
+        weights.extend(vertex.edges.iter().map(|edge| {
+            edge.probability as f64
+                * constraints.success_probability(
+                    &self.graph,
+                    self.constraint_state,
+                    edge.vertex_index,
+                )
+        }));
+        if weights.iter().all(|x| *x <= 0.0) {
+            for (edge, weight) in vertex.edges.iter().zip(weights.iter_mut()) {
+                if self.edge_allowed(edge) {
+                    *weight = edge.count as f64;
+                }
+            }
+        }
+        self.avoid_dead_ends(&mut weights);
+        self.shape_weights(&mut weights);
+
+        let selection = sample_weighted(&mut self.rng, &weights);
+        self.weights = weights;
+
+        selection
+    }
+
+    /// Samples an edge of the current vertex by its probability, adjusted by `temperature`, `top_k` and `top_p`.
+    fn sample_shaped(&mut self) -> Option<usize> {
+        let vertex = &self.graph.vertices[self.position];
+
+        let mut weights = std::mem::take(&mut self.weights);
+        weights.clear();
+        weights.extend(vertex.edges.iter().map(|x| x.probability as f64));
+        self.avoid_dead_ends(&mut weights);
+        self.shape_weights(&mut weights);
+
+        let selection = sample_weighted(&mut self.rng, &weights);
+        self.weights = weights;
+
+        selection
+    }
+
+    /// Applies `temperature`, `top_k` and `top_p` to `weights`, which must be proportional to the probabilities of
+    /// the current vertex's edges, times any factor the rest of the configuration calls for. Edges are ranked by
+    /// their probability, and truncation always keeps the most likely edge with a positive weight.
+    fn shape_weights(&self, weights: &mut [f64]) {
+        let vertex = self.get_current_vertex();
+
+        if let Some(temperature) = self.configuration.temperature {
+            // Relative to the most likely edge, so that low temperatures don't make every weight underflow.
+            let max_probability = vertex.edges.last().map_or(1.0, |x| x.probability as f64);
+            for (edge, weight) in vertex.edges.iter().zip(weights.iter_mut()) {
+                if *weight > 0.0 {
+                    *weight *=
+                        (edge.probability as f64 / max_probability).powf(1.0 / temperature - 1.0);
+                }
+            }
+        }
+
+        let (top_k, top_p) = (self.configuration.top_k, self.configuration.top_p);
+        if top_k.is_none() && top_p.is_none() {
+            return;
+        }
+
+        let weight_sum: f64 = weights.iter().sum();
+        let mut kept_count = 0;
+        let mut kept_weight = 0.0;
+        // Edges are sorted by ascending probability, so the most likely ones are at the end.
+        for index in (0..weights.len()).rev() {
+            if weights[index] <= 0.0 {
+                continue;
+            }
+
+            if top_k.is_some_and(|x| kept_count >= x)
+                || top_p.is_some_and(|x| kept_weight >= x * weight_sum)
+            {
+                weights[index] = 0.0;
+                continue;
+            }
+
+            kept_count += 1;
+            kept_weight += weights[index];
+        }
+    }
+
+    /// Like the min length sampler, removes edges leading to dead ends from `weights` while the built sequence is
+    /// shorter than `min_length`, unless nothing else is left. The remaining edges keep their relative weights.
+    fn avoid_dead_ends(&self, weights: &mut [f64]) {
+        let length = self.built_sequence.len();
+        if self.configuration.min_length.is_none_or(|x| length >= x) {
+            return;
+        }
+
+        let vertex = self.get_current_vertex();
+        let is_dead_end = |edge: &Edge| self.graph.vertices[edge.vertex_index].edges.is_empty();
+        if vertex
+            .edges
+            .iter()
+            .zip(weights.iter())
+            .any(|(edge, weight)| *weight > 0.0 && !is_dead_end(edge))
+        {
+            for (edge, weight) in vertex.edges.iter().zip(weights.iter_mut()) {
+                if is_dead_end(edge) {
+                    *weight = 0.0;
+                }
+            }
+        }
+    }
+
+    /// Whether taking `edge` from the current vertex keeps `banned_values` and `required_sequences` satisfiable.
+    fn edge_allowed(&self, edge: &Edge) -> bool {
+        match &self.constraints {
+            Some(constraints) => {
+                constraints.allows(&self.graph, self.constraint_state, edge.vertex_index)
+            }
+            None => true,
+        }
+    }
+
+    /// Whether `sequence` is fewer than `min_edit_distance` edits away from any training sequence.
+    fn resembles_training_sequence(&self, sequence: &[T]) -> bool {
+        match (
+            self.configuration.min_edit_distance,
+            &self.sequence_index,
+            &self.graph.sequences,
+        ) {
+            (Some(min_edit_distance), Some(index), Some(sequences)) => {
+                index.contains_similar(sequences, sequence, min_edit_distance)
+            }
+            _ => false,
+        }
+    }
+
+    fn random_position(&mut self) -> usize {
+        // Random starts are limited to the vertices the constraints can be satisfied from, if any.
+        if let Some(constraints) = &self.constraints {
+            let state = constraints.initial_state();
+            let viable_count = (0..self.graph.vertices.len())
+                .filter(|x| constraints.is_viable(*x, state))
+                .count();
+            if viable_count > 0 {
+                let selected = self.rng.usize(0..viable_count);
+                return (0..self.graph.vertices.len())
+                    .filter(|x| constraints.is_viable(*x, state))
+                    .nth(selected)
+                    .unwrap();
+            }
+        }
+
+        self.rng.usize(0..self.graph.vertices.len())
+    }
+
+    fn get_current_vertex(&self) -> &Vertex<T> {
+        self.graph.vertices.get(self.position).unwrap()
+    }
+}
+
+/// Removes repeated sequences, keeping the first occurrence of each.
+fn deduplicate<T: Eq + Hash>(mut sequences: Vec<Vec<T>>) -> Vec<Vec<T>> {
+    let is_first: Vec<bool> = {
+        let mut seen: HashSet<&Vec<T>> = HashSet::new();
+        sequences.iter().map(|x| seen.insert(x)).collect()
+    };
+
+    let mut is_first = is_first.into_iter();
+    sequences.retain(|_| is_first.next().unwrap_or(false));
+    sequences
+}
+
+/// "Roulette Wheel Selection" of an index into `weights`, which don't need to be normalized.
+/// Returns `None` if no weight is positive.
+fn sample_weighted(rng: &mut fastrand::Rng, weights: &[f64]) -> Option<usize> {
+    let weight_sum: f64 = weights.iter().sum();
+    if weight_sum <= 0.0 {
+        return None;
+    }
+
+    let random_value = rng.f64() * weight_sum;
+    let mut prob_sum = 0.0;
+    for (index, weight) in weights.iter().enumerate() {
+        prob_sum += weight;
+        if random_value < prob_sum {
+            return Some(index);
+        }
+    }
+
+    // Floating point addition error might keep the sum from reaching the random value. In that case, pick the
+    // last edge that can be selected at all.
+    weights.iter().rposition(|x| *x > 0.0)
+}
+
+/// Indices into a graph's training sequences by their hash and by their length, so that checking for exact
+/// reproductions takes constant time, and checking for similar sequences only compares those of similar length.
+struct SequenceIndex {
+    hasher: RandomState,
+    by_hash: HashMap<u64, Vec<usize>>,
+    by_length: Vec<Vec<usize>>,
+}
+
+impl SequenceIndex {
+    fn new<T: Hash>(sequences: &[Vec<T>]) -> Self {
+        let mut out = Self {
+            hasher: RandomState::new(),
+            by_hash: HashMap::new(),
+            by_length: Vec::new(),
+        };
+
+        for (index, sequence) in sequences.iter().enumerate() {
+            out.by_hash
+                .entry(out.hasher.hash_one(sequence))
+                .or_default()
+                .push(index);
+
+            if out.by_length.len() <= sequence.len() {
+                out.by_length.resize(sequence.len() + 1, Vec::new());
+            }
+            out.by_length[sequence.len()].push(index);
+        }
+
+        out
+    }
+
+    /// Whether any of `sequences`, the ones the index was built from, is fewer than `limit` edits away from
+    /// `sequence`. `limit` must be positive.
+    fn contains_similar<T: Eq + Hash>(
+        &self,
+        sequences: &[Vec<T>],
+        sequence: &[T],
+        limit: usize,
+    ) -> bool {
+        let is_reproduction = self
+            .by_hash
+            .get(&self.hasher.hash_one(sequence))
+            .is_some_and(|x| x.iter().any(|x| sequences[*x] == sequence));
+        if is_reproduction || limit == 1 {
+            return is_reproduction;
+        }
+
+        // Sequences whose length differs by `limit` or more are at least that many edits away.
+        let lengths = sequence.len().saturating_sub(limit - 1)..sequence.len() + limit;
+        lengths
+            .filter_map(|x| self.by_length.get(x))
+            .flatten()
+            .any(|x| is_edit_distance_below(sequence, &sequences[*x], limit))
+    }
+}
+
+/// Whether the Levenshtein distance between `a` and `b` is less than `limit`, stopping as soon as it can't be.
+fn is_edit_distance_below<T: PartialEq>(a: &[T], b: &[T], limit: usize) -> bool {
+    if a.len().abs_diff(b.len()) >= limit {
+        return false;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current: Vec<usize> = vec![0; b.len() + 1];
+    for (index, a_value) in a.iter().enumerate() {
+        current[0] = index + 1;
+        for (b_index, b_value) in b.iter().enumerate() {
+            let substitution = previous[b_index] + (a_value != b_value) as usize;
+            current[b_index + 1] = substitution
+                .min(previous[b_index + 1] + 1)
+                .min(current[b_index] + 1);
+        }
+
+        // The smallest distance in a row never decreases in the next one.
+        if current.iter().all(|x| *x >= limit) {
+            return false;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()] < limit
+}
+
+pub enum GraphStepperOut<T> {
+    Reached(Vec<T>),
+    Timeout(Vec<T>),
+    /// Not conforming to configuration, but out of edges pointing to anything other than end states.
+    Exhausted(Vec<T>),
+    /// Reached an end state, but too close to a training sequence, see
+    /// [`GraphStepperConfiguration::min_edit_distance`].
+    Rejected(Vec<T>),
+}
+
+impl<T> GraphStepperOut<T> {
+    pub fn into_inner(self) -> Vec<T> {
+        match self {
+            GraphStepperOut::Timeout(x) => x,
+            GraphStepperOut::Reached(x) => x,
+            GraphStepperOut::Exhausted(x) => x,
+            GraphStepperOut::Rejected(x) => x,
+        }
+    }
+
+    /// Collects the generated values into any sink, e.g. a `String` for char sequences.
+    pub fn collect<B: FromIterator<T>>(self) -> B {
+        self.into_inner().into_iter().collect()
+    }
+}
+
+impl<T: Display> Display for GraphStepperOut<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let out = match self {
+            GraphStepperOut::Timeout(x) => x,
+            GraphStepperOut::Reached(x) => x,
+            GraphStepperOut::Exhausted(x) => x,
+            GraphStepperOut::Rejected(x) => x,
+        };
+
+        for value in out {
+            f.write_fmt(format_args!("{}", value))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn names_graph() -> Graph<char> {
+        let mut constructor = GraphConstructor::with_order(2);
+        for name in ["anna", "anne", "hanna", "hannes", "jan", "jana", "jane"] {
+            constructor.register_whole_sequence(name.chars());
+        }
+        constructor.construct()
+    }
+
+    #[test]
+    fn cloned_steppers_generate_different_sequences() {
+        let graph = Arc::new(names_graph());
+        let mut stepper =
+            GraphStepper::with_seed(graph, GraphStepperConfiguration::default(), 7).unwrap();
+        let mut clone = stepper.clone();
+
+        let sequences: Vec<Vec<char>> = (0..32)
+            .map(|_| stepper.step_until_end_state(64).unwrap().into_inner())
+            .collect();
+        let cloned_sequences: Vec<Vec<char>> = (0..32)
+            .map(|_| clone.step_until_end_state(64).unwrap().into_inner())
+            .collect();
+        assert_ne!(sequences, cloned_sequences);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialized_graphs_recompute_samplers() {
+        let graph = names_graph();
+        let bytes = bincode::serialize(&graph).unwrap();
+        let deserialized: Graph<char> = bincode::deserialize(&bytes).unwrap();
+
+        let configuration = GraphStepperConfiguration {
+            min_length: Some(4),
+            ..Default::default()
+        };
+        let sample = |graph: Graph<char>| {
+            let mut stepper =
+                GraphStepper::with_seed(Arc::new(graph), configuration.clone(), 7).unwrap();
+            (0..32)
+                .map(|_| stepper.step_until_end_state(64).unwrap().into_inner())
+                .collect::<Vec<Vec<char>>>()
+        };
+        assert_eq!(sample(graph), sample(deserialized));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unseen transition probability must be in (0, 1]")]
+    fn score_rejects_invalid_unseen_probability() {
+        names_graph().score(&['x'], UnseenTransitions::Probability(0.0));
+    }
+
+    #[test]
+    fn weighted_merge_drops_sequences_of_unweighted_graphs() {
+        let keep = |names: &[&str]| {
+            let mut constructor = GraphConstructor::with_order(1).keep_sequences();
+            for name in names {
+                constructor.register_whole_sequence(name.chars());
+            }
+            constructor.construct()
+        };
+        let (used, unused) = (keep(&["anna"]), keep(&["otto"]));
+
+        let merged = Graph::weighted_merge(&[(&used, 1.0), (&unused, 0.0)]).unwrap();
+        assert_eq!(merged.sequences(), Some(&["anna".chars().collect()][..]));
+    }
+
+    #[test]
+    fn start_value_continues_sequences_beginning_with_it() {
+        let graph = Arc::new(names_graph());
+        let configuration = GraphStepperConfiguration {
+            start_value: Some('h'),
+            ..Default::default()
+        };
+        let mut stepper = GraphStepper::with_seed(graph.clone(), configuration, 7).unwrap();
+
+        // Only "hanna" and "hannes" begin with 'h', so every sequence continues with "an".
+        for _ in 0..64 {
+            let sequence = stepper.step_until_end_state(64).unwrap().into_inner();
+            assert!(sequence.starts_with(&['a', 'n']));
+        }
+
+        // No sequence begins with 'n', even though it occurs in the middle of some.
+        let configuration = GraphStepperConfiguration {
+            start_value: Some('n'),
+            ..Default::default()
+        };
+        assert!(matches!(
+            GraphStepper::new(graph, configuration),
+            Err(GraphStepperError::InvalidParameter(
+                InvalidConfigurationParameter::StartValue
+            ))
+        ));
+    }
+
+    #[test]
+    fn exact_length_sequences_have_that_length() {
+        let configuration = GraphStepperConfiguration {
+            exact_length: Some(5),
+            ..Default::default()
+        };
+        let mut stepper =
+            GraphStepper::with_seed(Arc::new(names_graph()), configuration, 7).unwrap();
+
+        for _ in 0..64 {
+            let GraphStepperOut::Reached(sequence) = stepper.step_until_end_state(64).unwrap()
+            else {
+                panic!("Sequences of a possible exact length should always be completed");
+            };
+            assert_eq!(sequence.len(), 5);
+        }
+    }
+
+    #[test]
+    fn impossible_exact_lengths_are_rejected() {
+        let graph = Arc::new(names_graph());
+        for (exact_length, prefix) in [(0, ""), (2, "han"), (3, "hann")] {
+            let configuration = GraphStepperConfiguration {
+                exact_length: Some(exact_length),
+                prefix: prefix.chars().collect(),
+                ..Default::default()
+            };
+
+            assert!(matches!(
+                GraphStepper::new(graph.clone(), configuration),
+                Err(GraphStepperError::InvalidParameter(
+                    InvalidConfigurationParameter::ExactLength
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn constrained_sequences_contain_required_and_no_banned_values() {
+        let configuration = GraphStepperConfiguration {
+            banned_values: vec!['e'],
+            required_sequences: vec!["nn".chars().collect()],
+            ..Default::default()
+        };
+        let mut stepper =
+            GraphStepper::with_seed(Arc::new(names_graph()), configuration, 7).unwrap();
+
+        for _ in 0..64 {
+            let GraphStepperOut::Reached(sequence) = stepper.step_until_end_state(64).unwrap()
+            else {
+                panic!("Constrained sequences should always be completed");
+            };
+            assert!(!sequence.contains(&'e'));
+            assert!(sequence.windows(2).any(|x| x == ['n', 'n']));
+        }
+    }
+
+    #[test]
+    fn unsatisfiable_constraints_are_rejected() {
+        let configuration = GraphStepperConfiguration {
+            banned_values: vec!['n'],
+            required_sequences: vec!["an".chars().collect()],
+            ..Default::default()
+        };
+        let stepper = GraphStepper::new(Arc::new(names_graph()), configuration);
+
+        assert!(matches!(
+            stepper,
+            Err(GraphStepperError::InvalidParameter(
+                InvalidConfigurationParameter::Constraints
+            ))
+        ));
+    }
+
+    #[test]
+    fn weighted_merge_honors_weights_of_small_shares() {
+        let build = |names: &[&str]| {
+            let mut constructor = GraphConstructor::with_order(1);
+            for name in names {
+                constructor.register_whole_sequence(name.chars());
+            }
+            constructor.construct()
+        };
+        let large = build(&["b", "c", "d", "e", "f", "g", "h", "i", "j", "k"]);
+        let small = build(&["a"]);
+
+        let merged = Graph::weighted_merge(&[(&large, 0.01), (&small, 0.99)]).unwrap();
+        let start = &merged.vertices[merged.start_vertex().unwrap()];
+        let share = start
+            .edges
+            .iter()
+            .find(|x| *merged.vertices[x.vertex_index].value() == Symbol::Value('a'))
+            .unwrap()
+            .probability;
+        assert!((share - 0.99).abs() < 0.001);
+    }
+
+    #[test]
+    fn neutral_top_p_keeps_min_length_distribution() {
+        let graph = Arc::new(names_graph());
+        let frequencies = |top_p: Option<f64>| {
+            let configuration = GraphStepperConfiguration {
+                min_length: Some(4),
+                top_p,
+                ..Default::default()
+            };
+            let mut stepper = GraphStepper::with_seed(graph.clone(), configuration, 7).unwrap();
+
+            let mut counts: HashMap<Vec<char>, f64> = HashMap::new();
+            for _ in 0..50_000 {
+                let sequence = stepper.step_until_end_state(64).unwrap().into_inner();
+                *counts.entry(sequence).or_default() += 1.0 / 50_000.0;
+            }
+            counts
+        };
+
+        let (sampled, shaped) = (frequencies(None), frequencies(Some(1.0)));
+        for (sequence, frequency) in sampled.iter() {
+            let shaped_frequency = shaped.get(sequence).copied().unwrap_or(0.0);
+            assert!((frequency - shaped_frequency).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn edit_distance_is_compared_to_limit() {
+        let distance_below = |a: &str, b: &str, limit: usize| {
+            let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+            is_edit_distance_below(&a, &b, limit)
+        };
+
+        // "kitten" and "sitting" are 3 edits apart.
+        assert!(distance_below("kitten", "sitting", 4));
+        assert!(!distance_below("kitten", "sitting", 3));
+        assert!(distance_below("", "abc", 4));
+        assert!(!distance_below("", "abc", 3));
+        assert!(distance_below("anna", "anna", 1));
+        assert!(!distance_below("anna", "anne", 1));
+    }
+
+    #[test]
+    fn sequence_index_finds_similar_sequences() {
+        let sequences: Vec<Vec<char>> = ["anna", "hannes", "jan"]
+            .iter()
+            .map(|x| x.chars().collect())
+            .collect();
+        let index = SequenceIndex::new(&sequences);
+        let contains_similar = |sequence: &str, limit: usize| {
+            let sequence: Vec<char> = sequence.chars().collect();
+            index.contains_similar(&sequences, &sequence, limit)
+        };
+
+        assert!(contains_similar("anna", 1));
+        assert!(!contains_similar("anne", 1));
+        assert!(contains_similar("anne", 2));
+        // One insertion away from "hannes", but only found by looking at longer sequences.
+        assert!(contains_similar("hanne", 2));
+        assert!(contains_similar("jane", 2));
+        assert!(!contains_similar("otto", 3));
+    }
+
+    #[test]
+    fn training_sequences_are_rejected() {
+        let names = ["anna", "anne", "hanna", "hannes", "jan", "jana", "jane"];
+        let mut constructor = GraphConstructor::with_order(2).keep_sequences();
+        for name in names {
+            constructor.register_whole_sequence(name.chars());
+        }
+        let configuration = GraphStepperConfiguration {
+            min_edit_distance: Some(1),
+            ..Default::default()
+        };
+        let mut stepper =
+            GraphStepper::with_seed(Arc::new(constructor.construct()), configuration, 7).unwrap();
+
+        for _ in 0..256 {
+            let out = stepper.step_until_end_state(64).unwrap();
+            let is_training_sequence = names.contains(&out.to_string().as_str());
+            assert_eq!(
+                is_training_sequence,
+                matches!(out, GraphStepperOut::Rejected(_))
+            );
+        }
+    }
+
+    #[test]
+    fn beam_search_without_width_is_empty() {
+        assert!(names_graph().beam_search(0, 16).is_empty());
+    }
+
+    #[test]
+    fn beam_search_sorts_by_descending_log_probability() {
+        let sequences = names_graph().beam_search(5, 16);
+
+        assert_eq!(sequences.len(), 5);
+        for pair in sequences.windows(2) {
+            assert!(pair[0].log_probability >= pair[1].log_probability);
+        }
     }
 }
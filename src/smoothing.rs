@@ -0,0 +1,585 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Graph, Symbol};
+
+/// How probability is assigned to transitions that don't occur in the dataset when scoring sequences with
+/// [`Graph::score`]. Selected with [`GraphConstructor::smoothing`](crate::GraphConstructor::smoothing). Stepping
+/// is unaffected and only ever takes observed transitions.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Smoothing {
+    /// Unseen transitions have a probability of 0.
+    #[default]
+    None,
+    /// Adds this amount to the count of every possible transition, seen or not. `Additive(1.0)` is Laplace
+    /// smoothing. Must be positive.
+    Additive(f64),
+    /// Interpolates with shorter contexts, weighted by how many distinct values follow each context.
+    WittenBell,
+    /// Interpolated Kneser-Ney with this absolute discount, usually 0.75. Shorter contexts are weighted by the number
+    /// of distinct contexts a value follows, rather than how often it occurs. The discount must be in `(0, 1]`.
+    KneserNey(f64),
+    /// Katz backoff: the counts of rare transitions are discounted using Good-Turing estimates, and unseen
+    /// transitions back off to shorter contexts. Unlike in plain Katz backoff, frequent counts lose half a count, so
+    /// that contexts only followed by frequent values still leave some probability for unseen transitions.
+    Katz,
+}
+
+impl Smoothing {
+    /// # Panics
+    /// Panics if the amount of [`Smoothing::Additive`] isn't positive, or the discount of [`Smoothing::KneserNey`]
+    /// isn't in `(0, 1]`.
+    pub(crate) fn assert_valid(&self) {
+        match self {
+            Smoothing::Additive(amount) => assert!(
+                amount.is_finite() && *amount > 0.0,
+                "Additive smoothing amount must be positive"
+            ),
+            Smoothing::KneserNey(discount) => assert!(
+                *discount > 0.0 && *discount <= 1.0,
+                "Kneser-Ney discount must be in (0, 1]"
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Counts up to which Katz backoff discounts transitions using Good-Turing estimates. More frequent counts are
+/// considered reliable.
+const KATZ_MAX_COUNT: usize = 5;
+
+/// Maps contexts to their index in a level of [`SmoothingModel`].
+type ContextIndices<T> = HashMap<Vec<Symbol<T>>, usize>;
+
+/// Statistics of contexts shorter than a graph's order, derived from its transition counts, along with whatever
+/// else the smoothing strategy needs precomputed.
+pub(crate) struct SmoothingModel<T> {
+    /// Contexts shorter than the graph's order, indexed by their length. Empty for additive smoothing.
+    levels: Vec<Vec<LowerContext<T>>>,
+    /// Maps each context in `levels` to its index there, so that unseen contexts don't require a linear scan.
+    context_indices: Vec<ContextIndices<T>>,
+    /// For each vertex, the index of its context without the first symbol in the longest level.
+    vertex_backoffs: Vec<usize>,
+    /// For Katz backoff, how much of each vertex's probability is left for unseen transitions.
+    vertex_backoff_weights: Vec<f64>,
+    /// For Katz backoff, the discount factor of counts from 1 to `KATZ_MAX_COUNT`, indexed by context length.
+    katz_discounts: Vec<Vec<f64>>,
+    /// The number of possible next symbols: every value, the end of a sequence and one for unknown values.
+    vocabulary_size: usize,
+}
+
+struct LowerContext<T> {
+    context: Vec<Symbol<T>>,
+    transitions: Vec<LowerTransition<T>>,
+    count_sum: usize,
+    continuation_sum: usize,
+    /// Index of the context without its first symbol in the next shorter level. `None` for the empty context.
+    backoff: Option<usize>,
+    backoff_weight: f64,
+}
+
+struct LowerTransition<T> {
+    symbol: Symbol<T>,
+    /// How often the symbol follows the context, summed over all longer contexts ending with it.
+    count: usize,
+    /// The number of distinct symbols preceding the context when followed by the symbol.
+    continuation: usize,
+}
+
+/// The counts of a context a smoothed probability is derived from.
+struct Counts {
+    count: usize,
+    sum: usize,
+    distinct: usize,
+}
+
+impl<T: Eq + Hash + Clone> SmoothingModel<T> {
+    pub(crate) fn new(graph: &Graph<T>, smoothing: Smoothing) -> Self {
+        let vocabulary: HashSet<&Symbol<T>> = graph
+            .vertices
+            .iter()
+            .map(|x| x.value())
+            .filter(|x| !matches!(x, Symbol::Start))
+            .chain([&Symbol::End])
+            .collect();
+
+        let mut out = Self {
+            levels: Vec::new(),
+            context_indices: Vec::new(),
+            vertex_backoffs: Vec::new(),
+            vertex_backoff_weights: Vec::new(),
+            katz_discounts: Vec::new(),
+            vocabulary_size: vocabulary.len() + 1,
+        };
+        if matches!(smoothing, Smoothing::Additive(_)) {
+            return out;
+        }
+
+        // The longest level is derived from the vertices, and every other level from the next longer one.
+        let mut builder = LevelBuilder::default();
+        for vertex in graph.vertices.iter() {
+            let index = builder.find_or_insert(&vertex.context[1..]);
+            out.vertex_backoffs.push(index);
+            for edge in vertex.edges.iter() {
+                let symbol = graph.vertices[edge.vertex_index].value();
+                builder.add(index, symbol, edge.count);
+            }
+        }
+
+        let mut levels: Vec<Vec<LowerContext<T>>> = Vec::with_capacity(graph.order);
+        for _ in 0..graph.order {
+            let (mut level, context_indices) = builder.finish();
+            out.context_indices.push(context_indices);
+            builder = LevelBuilder::default();
+
+            if level.first().is_some_and(|x| !x.context.is_empty()) {
+                for context in level.iter_mut() {
+                    let index = builder.find_or_insert(&context.context[1..]);
+                    context.backoff = Some(index);
+                    for transition in context.transitions.iter() {
+                        builder.add(index, &transition.symbol, transition.count);
+                    }
+                }
+            }
+
+            levels.push(level);
+        }
+        levels.reverse();
+        out.levels = levels;
+        out.context_indices.reverse();
+
+        if smoothing == Smoothing::Katz {
+            out.update_katz(graph);
+        }
+
+        out
+    }
+
+    /// The smoothed probability of `symbol` following a context that isn't a vertex of the graph, estimated from
+    /// the longest shorter context that occurs in the dataset.
+    pub(crate) fn context_probability(
+        &self,
+        smoothing: Smoothing,
+        context: &[Symbol<T>],
+        symbol: &Symbol<T>,
+    ) -> f64 {
+        for length in (0..self.levels.len()).rev() {
+            let suffix = &context[context.len() - length..];
+            if let Some(index) = self.context_indices[length].get(suffix) {
+                return self.lower_probability(smoothing, length, *index, symbol);
+            }
+        }
+
+        1.0 / self.vocabulary_size as f64
+    }
+
+    /// Computes the Good-Turing discounts for each context length, and then the backoff weights from the shortest
+    /// contexts up, since each depends on the probabilities of the next shorter context.
+    fn update_katz(&mut self, graph: &Graph<T>) {
+        self.katz_discounts = self
+            .levels
+            .iter()
+            .map(|level| {
+                katz_discounts(
+                    level
+                        .iter()
+                        .flat_map(|x| x.transitions.iter())
+                        .map(|x| x.count),
+                )
+            })
+            .collect();
+        self.katz_discounts.push(katz_discounts(
+            graph
+                .vertices
+                .iter()
+                .flat_map(|x| x.edges.iter())
+                .map(|x| x.count),
+        ));
+
+        for length in 0..self.levels.len() {
+            let weights: Vec<f64> = self.levels[length]
+                .iter()
+                .map(|context| {
+                    let seen = context.transitions.iter().map(|x| (&x.symbol, x.count));
+                    self.katz_backoff_weight(length, seen, context.count_sum, |symbol| {
+                        self.shorter_probability(Smoothing::Katz, length, context.backoff, symbol)
+                    })
+                })
+                .collect();
+
+            for (context, weight) in self.levels[length].iter_mut().zip(weights) {
+                context.backoff_weight = weight;
+            }
+        }
+
+        self.vertex_backoff_weights = graph
+            .vertices
+            .iter()
+            .zip(self.vertex_backoffs.iter())
+            .map(|(vertex, backoff)| {
+                let seen = vertex
+                    .edges
+                    .iter()
+                    .map(|x| (graph.vertices[x.vertex_index].value(), x.count));
+                let count_sum = vertex.edges.iter().map(|x| x.count).sum();
+                self.katz_backoff_weight(graph.order, seen, count_sum, |symbol| {
+                    self.lower_probability(Smoothing::Katz, graph.order - 1, *backoff, symbol)
+                })
+            })
+            .collect();
+    }
+
+    /// The probability left over by discounting the seen transitions, relative to the probability the shorter
+    /// context assigns to the unseen ones.
+    fn katz_backoff_weight<'a>(
+        &self,
+        length: usize,
+        seen: impl Iterator<Item = (&'a Symbol<T>, usize)> + Clone,
+        count_sum: usize,
+        shorter_probability: impl Fn(&Symbol<T>) -> f64,
+    ) -> f64
+    where
+        T: 'a,
+    {
+        if count_sum == 0 {
+            return 1.0;
+        }
+
+        let discounted: f64 = seen
+            .clone()
+            .map(|(_, count)| self.katz_discount(length, count) * count as f64 / count_sum as f64)
+            .sum();
+        let shorter: f64 = seen.map(|(symbol, _)| shorter_probability(symbol)).sum();
+
+        if shorter >= 1.0 {
+            return 0.0;
+        }
+        (1.0 - discounted).max(0.0) / (1.0 - shorter)
+    }
+}
+
+impl<T: PartialEq> SmoothingModel<T> {
+    /// The smoothed probability of `symbol` following the vertex with index `vertex_index`.
+    pub(crate) fn vertex_probability(
+        &self,
+        graph: &Graph<T>,
+        smoothing: Smoothing,
+        vertex_index: usize,
+        symbol: &Symbol<T>,
+    ) -> f64 {
+        let vertex = &graph.vertices[vertex_index];
+        let counts = Counts {
+            count: vertex
+                .edges
+                .iter()
+                .find(|x| graph.vertices[x.vertex_index].value() == symbol)
+                .map_or(0, |x| x.count),
+            sum: vertex.edges.iter().map(|x| x.count).sum(),
+            distinct: vertex.edges.len(),
+        };
+
+        let backoff_weight = self
+            .vertex_backoff_weights
+            .get(vertex_index)
+            .copied()
+            .unwrap_or(1.0);
+        self.combine(smoothing, graph.order, counts, backoff_weight, || {
+            self.lower_probability(
+                smoothing,
+                graph.order - 1,
+                self.vertex_backoffs[vertex_index],
+                symbol,
+            )
+        })
+    }
+
+    fn lower_probability(
+        &self,
+        smoothing: Smoothing,
+        length: usize,
+        index: usize,
+        symbol: &Symbol<T>,
+    ) -> f64 {
+        let context = &self.levels[length][index];
+        let transition = context.transitions.iter().find(|x| x.symbol == *symbol);
+
+        // Kneser-Ney estimates shorter contexts by how many contexts a value continues.
+        let counts = match smoothing {
+            Smoothing::KneserNey(_) => Counts {
+                count: transition.map_or(0, |x| x.continuation),
+                sum: context.continuation_sum,
+                distinct: context.transitions.len(),
+            },
+            _ => Counts {
+                count: transition.map_or(0, |x| x.count),
+                sum: context.count_sum,
+                distinct: context.transitions.len(),
+            },
+        };
+
+        self.combine(smoothing, length, counts, context.backoff_weight, || {
+            self.shorter_probability(smoothing, length, context.backoff, symbol)
+        })
+    }
+
+    /// The probability of `symbol` in the context one shorter than `length`, or the uniform distribution below
+    /// the empty context.
+    fn shorter_probability(
+        &self,
+        smoothing: Smoothing,
+        length: usize,
+        backoff: Option<usize>,
+        symbol: &Symbol<T>,
+    ) -> f64 {
+        match backoff {
+            Some(index) => self.lower_probability(smoothing, length - 1, index, symbol),
+            None => 1.0 / self.vocabulary_size as f64,
+        }
+    }
+
+    fn combine(
+        &self,
+        smoothing: Smoothing,
+        length: usize,
+        counts: Counts,
+        backoff_weight: f64,
+        shorter_probability: impl Fn() -> f64,
+    ) -> f64 {
+        let (count, sum, distinct) = (
+            counts.count as f64,
+            counts.sum as f64,
+            counts.distinct as f64,
+        );
+
+        match smoothing {
+            Smoothing::Additive(amount) => {
+                (count + amount) / (sum + amount * self.vocabulary_size as f64)
+            }
+            _ if counts.sum == 0 => shorter_probability(),
+            Smoothing::None => count / sum,
+            Smoothing::WittenBell => (count + distinct * shorter_probability()) / (sum + distinct),
+            Smoothing::KneserNey(discount) => {
+                (count - discount).max(0.0) / sum
+                    + discount * distinct / sum * shorter_probability()
+            }
+            Smoothing::Katz if counts.count > 0 => {
+                self.katz_discount(length, counts.count) * count / sum
+            }
+            Smoothing::Katz => backoff_weight * shorter_probability(),
+        }
+    }
+
+    fn katz_discount(&self, length: usize, count: usize) -> f64 {
+        match count {
+            1..=KATZ_MAX_COUNT => self.katz_discounts[length][count - 1],
+            _ => (count as f64 - 0.5) / count as f64,
+        }
+    }
+}
+
+/// Good-Turing discounts for counts from 1 to `KATZ_MAX_COUNT`, as used by Katz. Where the count-of-counts are too
+/// sparse for a sensible estimate, half a count is subtracted instead.
+fn katz_discounts(counts: impl Iterator<Item = usize>) -> Vec<f64> {
+    let mut count_of_counts = [0usize; KATZ_MAX_COUNT + 2];
+    for count in counts {
+        if count <= KATZ_MAX_COUNT + 1 {
+            count_of_counts[count] += 1;
+        }
+    }
+
+    let singletons = count_of_counts[1] as f64;
+    let cutoff_share =
+        (KATZ_MAX_COUNT + 1) as f64 * count_of_counts[KATZ_MAX_COUNT + 1] as f64 / singletons;
+
+    (1..=KATZ_MAX_COUNT)
+        .map(|count| {
+            let good_turing = (count + 1) as f64 * count_of_counts[count + 1] as f64
+                / (count as f64 * count_of_counts[count] as f64);
+            let discount = (good_turing - cutoff_share) / (1.0 - cutoff_share);
+
+            if discount.is_finite() && discount > 0.0 && discount < 1.0 {
+                discount
+            } else {
+                (count as f64 - 0.5) / count as f64
+            }
+        })
+        .collect()
+}
+
+/// Collects the contexts of one level, keeping them in insertion order so that models are deterministic.
+struct LevelBuilder<T> {
+    contexts: Vec<LowerContext<T>>,
+    context_indices: ContextIndices<T>,
+    transition_indices: HashMap<(usize, Symbol<T>), usize>,
+}
+
+impl<T> Default for LevelBuilder<T> {
+    fn default() -> Self {
+        Self {
+            contexts: Vec::new(),
+            context_indices: HashMap::new(),
+            transition_indices: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> LevelBuilder<T> {
+    fn find_or_insert(&mut self, context: &[Symbol<T>]) -> usize {
+        if let Some(index) = self.context_indices.get(context) {
+            return *index;
+        }
+
+        let index = self.contexts.len();
+        self.contexts.push(LowerContext {
+            context: context.to_vec(),
+            transitions: Vec::new(),
+            count_sum: 0,
+            continuation_sum: 0,
+            backoff: None,
+            backoff_weight: 1.0,
+        });
+        self.context_indices.insert(context.to_vec(), index);
+
+        index
+    }
+
+    /// Adds a transition of a longer context ending with the context at `index`, which counts as one more distinct
+    /// preceding symbol.
+    fn add(&mut self, index: usize, symbol: &Symbol<T>, count: usize) {
+        let transitions = &mut self.contexts[index].transitions;
+        match self.transition_indices.get(&(index, symbol.clone())) {
+            Some(x) => {
+                transitions[*x].count += count;
+                transitions[*x].continuation += 1;
+            }
+            None => {
+                self.transition_indices
+                    .insert((index, symbol.clone()), transitions.len());
+                transitions.push(LowerTransition {
+                    symbol: symbol.clone(),
+                    count,
+                    continuation: 1,
+                });
+            }
+        }
+    }
+
+    /// Returns the contexts along with the index of each of them.
+    fn finish(self) -> (Vec<LowerContext<T>>, ContextIndices<T>) {
+        let mut contexts = self.contexts;
+        for context in contexts.iter_mut() {
+            context.count_sum = context.transitions.iter().map(|x| x.count).sum();
+            context.continuation_sum = context.transitions.iter().map(|x| x.continuation).sum();
+        }
+
+        (contexts, self.context_indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GraphConstructor;
+
+    const NAMES: &str = include_str!("../benches/US_Census_1990_Frequent_Male_First_Names.txt");
+    const STRATEGIES: [Smoothing; 6] = [
+        Smoothing::Additive(1.0),
+        Smoothing::Additive(0.1),
+        Smoothing::WittenBell,
+        Smoothing::KneserNey(0.75),
+        Smoothing::KneserNey(1.0),
+        Smoothing::Katz,
+    ];
+    /// Doesn't occur in the dataset, so it stands in for every unknown value.
+    const UNKNOWN: char = '#';
+
+    fn graph(order: usize, smoothing: Smoothing) -> Graph<char> {
+        let mut constructor = GraphConstructor::with_order(order).smoothing(smoothing);
+        for name in NAMES.lines() {
+            constructor.register_whole_sequence(name.chars());
+        }
+        constructor.construct()
+    }
+
+    /// Every symbol that can follow a context: the values, the end of a sequence and an unknown value.
+    fn next_symbols(graph: &Graph<char>) -> Vec<Symbol<char>> {
+        let mut values: Vec<char> = graph.values().copied().collect();
+        values.sort();
+        values.dedup();
+        assert!(!values.contains(&UNKNOWN));
+
+        values
+            .into_iter()
+            .chain([UNKNOWN])
+            .map(Symbol::Value)
+            .chain([Symbol::End])
+            .collect()
+    }
+
+    fn assert_normalized(sum: f64, smoothing: Smoothing, context: &[Symbol<char>]) {
+        assert!(
+            (sum - 1.0).abs() < 1e-9,
+            "{smoothing:?} probabilities after {context:?} sum up to {sum}"
+        );
+    }
+
+    #[test]
+    fn vertex_probabilities_sum_to_one() {
+        for order in 1..=3 {
+            for smoothing in STRATEGIES {
+                let graph = graph(order, smoothing);
+                let model = graph.smoothing_model.as_ref().unwrap();
+                let symbols = next_symbols(&graph);
+
+                for (index, vertex) in graph.vertices.iter().enumerate() {
+                    let sum: f64 = symbols
+                        .iter()
+                        .map(|x| model.vertex_probability(&graph, smoothing, index, x))
+                        .sum();
+                    assert_normalized(sum, smoothing, &vertex.context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unseen_context_probabilities_sum_to_one() {
+        let contexts = [
+            vec![Symbol::Value('J'), Symbol::Value('o'), Symbol::Value('q')],
+            vec![Symbol::Value('q'), Symbol::Value('q'), Symbol::Value('o')],
+            vec![Symbol::Value(UNKNOWN); 3],
+        ];
+
+        for smoothing in STRATEGIES {
+            let graph = graph(3, smoothing);
+            let model = graph.smoothing_model.as_ref().unwrap();
+            let symbols = next_symbols(&graph);
+
+            for context in contexts.iter() {
+                assert!(graph.find_vertex(context).is_none());
+                let sum: f64 = symbols
+                    .iter()
+                    .map(|x| model.context_probability(smoothing, context, x))
+                    .sum();
+                assert_normalized(sum, smoothing, context);
+            }
+        }
+    }
+
+    #[test]
+    fn katz_backoff_weights_are_positive() {
+        let graph = graph(3, Smoothing::Katz);
+        let model = graph.smoothing_model.as_ref().unwrap();
+
+        assert!(model.vertex_backoff_weights.iter().all(|x| *x > 0.0));
+        for level in model.levels.iter() {
+            assert!(level.iter().all(|x| x.backoff_weight > 0.0));
+        }
+    }
+}